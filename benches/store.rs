@@ -0,0 +1,91 @@
+//! Compares the linear scan of `RingBuffer` against the indexed lookups of
+//! `IndexedBuffer` when looking up and pushing processed transactions
+//!
+//! Run with `cargo bench`
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use transaction_processor::datatypes::{
+    Amount, RingBuffer, StoredTransaction, Transaction, TransactionType,
+};
+use transaction_processor::store::IndexedBuffer;
+
+const CAPACITIES: [usize; 3] = [1_000, 10_000, 100_000];
+
+///Create a processed deposit with the given ID
+fn deposit(id: u32) -> StoredTransaction {
+    StoredTransaction::new(Transaction {
+        tx_type: TransactionType::Deposit,
+        client: (id % 100) as u16,
+        id,
+        amount: Some(Amount::from_raw(10_000)),
+    })
+}
+
+///Create full buffers of both kinds holding the transactions `0..capacity`
+fn filled(capacity: usize) -> (RingBuffer<StoredTransaction>, IndexedBuffer) {
+    let mut ring = RingBuffer::with_capacity(capacity);
+    let mut indexed = IndexedBuffer::with_capacity(capacity);
+    for id in 0..capacity as u32 {
+        ring.push(deposit(id));
+        indexed.push(deposit(id));
+    }
+    (ring, indexed)
+}
+
+///Look up transactions spread across the whole buffer, as disputes might
+fn bench_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for capacity in CAPACITIES {
+        let (ring, indexed) = filled(capacity);
+        //A stride coprime with the capacity visits every position in turn
+        let ids: Vec<u32> = (0..1_000)
+            .map(|i| ((i * 7_919) % capacity) as u32)
+            .collect();
+
+        group.bench_with_input(BenchmarkId::new("RingBuffer", capacity), &ids, |b, ids| {
+            b.iter(|| {
+                for id in ids {
+                    black_box(ring.get_by_tx(*id));
+                }
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("IndexedBuffer", capacity),
+            &ids,
+            |b, ids| {
+                b.iter(|| {
+                    for id in ids {
+                        black_box(indexed.get_by_tx(*id));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+///Push transactions into an already full buffer, so each push also evicts
+fn bench_push(c: &mut Criterion) {
+    let mut group = c.benchmark_group("push");
+    for capacity in CAPACITIES {
+        let (mut ring, mut indexed) = filled(capacity);
+        let mut next_id = capacity as u32;
+
+        group.bench_function(BenchmarkId::new("RingBuffer", capacity), |b| {
+            b.iter(|| {
+                ring.push(deposit(next_id));
+                next_id = next_id.wrapping_add(1);
+            })
+        });
+        group.bench_function(BenchmarkId::new("IndexedBuffer", capacity), |b| {
+            b.iter(|| {
+                black_box(indexed.push(deposit(next_id)));
+                next_id = next_id.wrapping_add(1);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookup, bench_push);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+use crate::datatypes::{Client, StoredTransaction, Transaction, TxIdSet};
+use crate::error::CheckpointError;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// The entire state of a `Ledger`, which can be saved at the end of one run and
+/// restored at the start of the next, so that the next run carries on exactly
+/// where the last one left off
+///
+/// Unlike a snapshot of client records, this includes the transactions retained
+/// for dispute lookups, the open disputes, and the IDs already processed, so a
+/// dispute in the next run can reference a transaction from this one.
+///
+/// Checkpoints are saved as JSON with a format version, which is checked when
+/// they're loaded so an incompatible checkpoint is rejected rather than misread.
+/// They also record whether the transaction history was kept by the store itself,
+/// so a checkpoint is never restored without the history it was taken with.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    version: u64,
+    ///Client records, in the order the clients were first seen
+    pub(crate) clients: Vec<Client>,
+    ///The transactions retained for dispute lookups
+    pub(crate) history: History,
+    ///The transactions currently under dispute
+    pub(crate) disputes: Vec<Transaction>,
+    ///The IDs of every processed deposit and withdrawal, for duplicate checks
+    pub(crate) seen: TxIdSet,
+    ///The IDs of transactions evicted from the transaction store
+    pub(crate) evicted: TxIdSet,
+    pub(crate) evicted_disputes: u64,
+}
+
+/// The transactions retained for dispute lookups, as saved in a checkpoint
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum History {
+    ///The transactions themselves, oldest first, for stores kept in memory
+    Retained(Vec<StoredTransaction>),
+    ///How much a store which persists its own transactions, such as a `DiskStore`,
+    ///had stored, since the transactions themselves are already in the store
+    External { len: u64 },
+}
+
+impl Checkpoint {
+    ///The version of the checkpoint format written by this build
+    pub const VERSION: u64 = 2;
+
+    pub(crate) fn new(
+        clients: Vec<Client>,
+        history: History,
+        disputes: Vec<Transaction>,
+        seen: TxIdSet,
+        evicted: TxIdSet,
+        evicted_disputes: u64,
+    ) -> Self {
+        Self {
+            version: Self::VERSION,
+            clients,
+            history,
+            disputes,
+            seen,
+            evicted,
+            evicted_disputes,
+        }
+    }
+
+    ///Write the checkpoint as JSON
+    pub fn save(&self, mut writer: impl io::Write) -> Result<(), CheckpointError> {
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    ///Read a checkpoint written by `save`
+    pub fn load(reader: impl io::Read) -> Result<Self, CheckpointError> {
+        //Check the version before anything else, since a checkpoint in another
+        //version may not deserialize at all
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        let version = value.get("version").and_then(serde_json::Value::as_u64);
+        if version != Some(Self::VERSION) {
+            return Err(CheckpointError::UnsupportedVersion {
+                version: version.unwrap_or(0),
+            });
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
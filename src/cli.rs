@@ -0,0 +1,259 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use transaction_processor::datatypes::Amount;
+use transaction_processor::{OutputOrder, Retention};
+
+/// Processes a CSV of transactions and outputs the final state of all clients
+///
+/// Running without a subcommand, e.g. `transaction-processor input.csv`, is the same as `process`.
+/// An input of `-` reads from stdin
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    ///The arguments of `process`, for when no subcommand is given
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    ///Process a CSV of transactions and output the final state of all clients
+    Process(ProcessArgs),
+    ///Check that every row of a CSV of transactions is well formed, without processing it
+    Validate(ValidateArgs),
+    ///Rebuild the state of a run which didn't finish from its journal, save it to a
+    ///checkpoint, and report where in the input to carry on from
+    Recover(RecoverArgs),
+    ///Replay a CSV of transactions up to a given transaction or row, and output the
+    ///state of clients right before it
+    AsOf(AsOfArgs),
+    ///Write a statement of every transaction of the given clients, with their
+    ///balances after each one
+    Statement(StatementArgs),
+}
+
+/// Options for how the ledger is set up, shared by every subcommand which builds one
+#[derive(Args)]
+pub struct LedgerArgs {
+    ///Carry on from the state saved in this checkpoint by an earlier run,
+    ///including the transactions retained for disputes and the open disputes
+    #[arg(long, value_name = "PATH", conflicts_with = "snapshot")]
+    pub resume: Option<PathBuf>,
+
+    ///Start from the client records in this CSV, in the same format they're
+    ///written out in, instead of from no clients
+    #[arg(long, value_name = "PATH", conflicts_with = "resume")]
+    pub snapshot: Option<PathBuf>,
+
+    ///How long processed transactions are kept for dispute lookups: a count,
+    ///`unbounded`, or an age such as `90s`, `30m`, or `2h`
+    #[arg(long, default_value_t = Retention::default())]
+    pub retention: Retention,
+
+    ///Keep every processed transaction in this directory instead of in memory,
+    ///replacing any history already there unless resuming
+    #[arg(long, value_name = "DIR")]
+    pub history_dir: Option<PathBuf>,
+
+    ///Reject deposits and withdrawals larger than this amount
+    #[arg(long, value_name = "AMOUNT")]
+    pub max_amount: Option<Amount>,
+}
+
+#[derive(Args)]
+pub struct ProcessArgs {
+    ///The CSVs of transactions to process, in order, into the same client records.
+    ///`-` reads from stdin
+    #[arg(value_name = "INPUT", required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    ///Save the full state at the end of the run to this checkpoint, for a later run to resume from
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint: Option<PathBuf>,
+
+    ///Journal every accepted transaction to this file as it's applied, so the run
+    ///can be recovered if it doesn't finish. The journal is emptied when the run finishes
+    #[arg(long, value_name = "PATH")]
+    pub journal: Option<PathBuf>,
+
+    ///How many journal entries to write between syncing the journal to disk
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    pub journal_sync_interval: usize,
+
+    ///The format to write client records in
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub format: OutputFormat,
+
+    ///The order to write client records in: `id`, `total`, or `first-seen`
+    #[arg(long, default_value_t = OutputOrder::default())]
+    pub order: OutputOrder,
+
+    ///Write client records to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    ///Write rejected rows to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub error_log: Option<PathBuf>,
+
+    ///Also write rejected rows to this file, with the original row and a reason code
+    #[arg(long, value_name = "PATH")]
+    pub rejects: Option<PathBuf>,
+
+    ///The format to write the rejects file in
+    #[arg(long, value_enum, default_value_t = RecordFormat::Csv)]
+    pub rejects_format: RecordFormat,
+
+    ///Write an event to this file for every applied transaction, with how it changed
+    ///the client's balances and the balances after it
+    #[arg(long, value_name = "PATH")]
+    pub events: Option<PathBuf>,
+
+    ///The format to write the events file in
+    #[arg(long, value_enum, default_value_t = RecordFormat::Csv)]
+    pub events_format: RecordFormat,
+
+    ///Print a summary of the run to stderr once all inputs have been processed
+    #[arg(long)]
+    pub summary: bool,
+
+    ///Write the summary of the run to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub summary_file: Option<PathBuf>,
+
+    ///Stop at the first rejected row instead of skipping it, and write no output.
+    ///Exits with 3 for a parse error, 4 for a malformed transaction,
+    ///5 for a transaction which breaks a rule, such as insufficient funds,
+    ///and 6 for a storage error
+    #[arg(long)]
+    pub strict: bool,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    ///The CSVs of transactions to check. `-` reads from stdin
+    #[arg(value_name = "INPUT", required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    ///Reject deposits and withdrawals larger than this amount
+    #[arg(long, value_name = "AMOUNT")]
+    pub max_amount: Option<Amount>,
+
+    ///Write rejected rows to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub error_log: Option<PathBuf>,
+
+    ///Also write rejected rows to this file, with the original row and a reason code
+    #[arg(long, value_name = "PATH")]
+    pub rejects: Option<PathBuf>,
+
+    ///The format to write the rejects file in
+    #[arg(long, value_enum, default_value_t = RecordFormat::Csv)]
+    pub rejects_format: RecordFormat,
+}
+
+#[derive(Args)]
+pub struct RecoverArgs {
+    ///The journal of the run to recover
+    #[arg(value_name = "JOURNAL")]
+    pub journal: PathBuf,
+
+    ///The options the run being recovered was started with, including the checkpoint
+    ///or snapshot it started from, if any
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    ///Save the recovered state to this checkpoint, which the rest of the input can be resumed from
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint: PathBuf,
+}
+
+#[derive(Args)]
+pub struct AsOfArgs {
+    ///The CSVs of transactions to replay, in order. `-` reads from stdin
+    #[arg(value_name = "INPUT", required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    ///Stop right before the first row with this transaction ID
+    #[arg(
+        long,
+        value_name = "ID",
+        required_unless_present = "before_row",
+        conflicts_with = "before_row"
+    )]
+    pub before_tx: Option<u32>,
+
+    ///Stop right before this row, counting the rows of every input in order from 1,
+    ///not including headers
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub before_row: Option<u64>,
+
+    ///Only output this client, which can be given more than once. Every client is
+    ///output if none are given
+    #[arg(long = "client", value_name = "ID")]
+    pub clients: Vec<u16>,
+
+    ///The options the input would be processed with, including the checkpoint or
+    ///snapshot it starts from, if any
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    ///The format to write client records in
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct StatementArgs {
+    ///The CSVs of transactions to process, in order. `-` reads from stdin
+    #[arg(value_name = "INPUT", required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    ///The client to write a statement for, which can be given more than once
+    #[arg(long = "client", value_name = "ID", required = true)]
+    pub clients: Vec<u16>,
+
+    ///The options the input would be processed with, including the checkpoint or
+    ///snapshot it starts from, if any
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    ///Write the statement to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    ///The format to write the statement in
+    #[arg(long, value_enum, default_value_t = RecordFormat::Csv)]
+    pub format: RecordFormat,
+}
+
+/// The formats client records can be written in
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    ///A header row followed by one row per client
+    Csv,
+    ///A single JSON array of client objects
+    Json,
+    ///One JSON client object per line
+    Jsonl,
+}
+
+/// The formats records written one row at a time, such as rejections, can be written in
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    ///A header row followed by one row per record
+    Csv,
+    ///One JSON object per line
+    Jsonl,
+}
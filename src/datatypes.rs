@@ -1,8 +1,194 @@
-use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+/// A monetary amount stored as a fixed-point integer with 4 decimal places
+/// of precision, so that arithmetic on balances is exact
+///
+/// Amounts are parsed from and written as plain decimal strings, e.g. `1.5` or `-0.0001`
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Amount(i64);
+
+impl Amount {
+    ///The number of decimal places an amount is stored with
+    pub const DECIMALS: u32 = 4;
+    ///The raw value representing one whole unit
+    pub const SCALE: i64 = 10_i64.pow(Self::DECIMALS);
+    ///An amount of zero
+    pub const ZERO: Amount = Amount(0);
+
+    ///Create an amount from its raw fixed-point value, e.g. `Amount::from_raw(15_000)` is `1.5`
+    pub const fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    ///Returns the raw fixed-point value of the amount
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    ///Adds two amounts, returning `None` on overflow
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    ///Subtracts two amounts, returning `None` on overflow
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+}
+
+/// The error returned when a string cannot be parsed as an `Amount`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountParseError {
+    value: String,
+    kind: AmountErrorKind,
+}
+
+/// The reasons a string cannot be parsed as an `Amount`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountErrorKind {
+    ///The string was empty or only a sign and decimal point
+    NoDigits,
+    ///The string was `NaN` or an infinity, which are valid floats but never valid amounts
+    NotFinite,
+    ///The string wasn't a plain decimal number, e.g. `1e5` or `1,5`
+    NotDecimal,
+    ///The string had more than 4 decimal places
+    TooPrecise,
+    ///The amount was too large to be stored
+    OutOfRange,
+}
+
+impl AmountParseError {
+    ///Returns why the string couldn't be parsed
+    pub fn kind(&self) -> AmountErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for AmountParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            AmountErrorKind::NoDigits => "no digits",
+            AmountErrorKind::NotFinite => "not finite",
+            AmountErrorKind::NotDecimal => "not a decimal number",
+            AmountErrorKind::TooPrecise => "more than 4 decimal places",
+            AmountErrorKind::OutOfRange => "out of range",
+        };
+        write!(f, "invalid amount: {:?} ({reason})", self.value)
+    }
+}
+
+impl std::error::Error for AmountParseError {}
+
+impl FromStr for Amount {
+    type Err = AmountParseError;
+
+    ///Parses a decimal string with at most 4 decimal places.
+    ///
+    ///Parsing is done on the digits directly rather than through `f64`
+    ///so that no precision is lost along the way
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |kind| AmountParseError {
+            value: s.to_string(),
+            kind,
+        };
+
+        //Split off the sign, if there is one
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+
+        //Split the whole and fractional parts. Either may be empty, e.g. `.5` or `5.`, but not both
+        //Values like NaN or inf may be valid floats, but are never valid amounts
+        if ["nan", "inf", "infinity"].contains(&unsigned.to_ascii_lowercase().as_str()) {
+            return Err(err(AmountErrorKind::NotFinite));
+        }
+
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(err(AmountErrorKind::NoDigits));
+        }
+        if !whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(err(AmountErrorKind::NotDecimal));
+        }
+        if fraction.len() > Self::DECIMALS as usize {
+            return Err(err(AmountErrorKind::TooPrecise));
+        }
+
+        //Accumulate the digits into the fixed-point value, padding the fraction with zeros
+        let padding = Self::DECIMALS as usize - fraction.len();
+        let raw = whole
+            .bytes()
+            .chain(fraction.bytes())
+            .chain(std::iter::repeat_n(b'0', padding))
+            .try_fold(0_i64, |acc, digit| {
+                acc.checked_mul(10)?.checked_add(i64::from(digit - b'0'))
+            })
+            .ok_or_else(|| err(AmountErrorKind::OutOfRange))?;
+
+        Ok(Amount(if negative { -raw } else { raw }))
+    }
+}
+
+impl fmt::Display for Amount {
+    ///Formats the amount with trailing zeros trimmed, but always
+    ///with at least one decimal place, e.g. `2.0`, `1.5` or `0.1234`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = Self::SCALE as u64;
+        let fraction = format!("{:04}", abs % scale);
+        let fraction = fraction.trim_end_matches('0');
+        let fraction = if fraction.is_empty() { "0" } else { fraction };
+        write!(f, "{sign}{}.{fraction}", abs / scale)
+    }
+}
+
+impl fmt::Debug for Amount {
+    ///Formats the amount as a decimal, so transactions in error messages
+    ///read the same as they did in the input
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl de::Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal amount with at most 4 decimal places")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
 
 /// Represents the type of a transaction
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum TransactionType {
     #[serde(rename = "deposit")]
     Deposit,
@@ -17,23 +203,79 @@ pub enum TransactionType {
 }
 
 /// Represents a transaction record from the input CSV
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
     pub client: u16,
     #[serde(rename = "tx")]
     pub id: u32,
-    pub amount: Option<f64>,
+    pub amount: Option<Amount>,
+}
+
+/// A row of the input CSV before its amount has been parsed, so that an amount
+/// which isn't a valid `Amount` is rejected by validation rather than as an
+/// unreadable row. See `validate::parse`
+#[derive(Debug, Deserialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub client: u16,
+    #[serde(rename = "tx")]
+    pub id: u32,
+    pub amount: Option<String>,
+}
+
+/// The lifecycle state of a processed deposit or withdrawal
+///
+/// A transaction starts out `Processed`, may be `Disputed` once, and a dispute
+/// ends in either `Resolved` or `ChargedBack`. Both of those are final, so a
+/// transaction can never be disputed twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TxState {
+    ///Returns the state a transaction moves to when a dispute, resolve, or chargeback
+    ///is applied to it, or `None` if that isn't a legal transition from this state
+    pub fn transition(self, action: &TransactionType) -> Option<TxState> {
+        match (self, action) {
+            (TxState::Processed, TransactionType::Dispute) => Some(TxState::Disputed),
+            (TxState::Disputed, TransactionType::Resolve) => Some(TxState::Resolved),
+            (TxState::Disputed, TransactionType::Chargeback) => Some(TxState::ChargedBack),
+            _ => None,
+        }
+    }
+}
+
+/// A processed transaction along with its current lifecycle state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    pub tx: Transaction,
+    pub state: TxState,
+}
+
+impl StoredTransaction {
+    ///Wrap a newly processed transaction
+    pub fn new(tx: Transaction) -> Self {
+        Self {
+            tx,
+            state: TxState::Processed,
+        }
+    }
 }
 
 /// Represents a client record, which is updated by transactions
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
     pub client: u16,
-    pub available: f64,
-    pub held: f64,
-    pub total: f64,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
 }
 
@@ -41,9 +283,9 @@ impl Client {
     pub fn new(client: u16) -> Self {
         Self {
             client,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
             locked: false,
         }
     }
@@ -73,26 +315,125 @@ impl<T> RingBuffer<T> {
     }
 
     ///Pop the oldest item from the buffer
-    ///Only needed in tests
-    #[cfg(test)]
     pub fn pop(&mut self) -> Option<T> {
         self.inside.pop_front()
     }
 
     ///Returns whether the buffer is empty
-    #[cfg(test)]
     pub fn is_empty(&self) -> bool {
         self.inside.is_empty()
     }
 }
 
-impl RingBuffer<Transaction> {
+impl RingBuffer<StoredTransaction> {
     ///Get a transaction by its ID from the buffer
     ///
     ///There may be more efficient ways to search for a transaction by ID, but
     ///since disputes should be rarer than deposits and withdrawals, it makes
     ///most sense to primarily optimize a buffer for adding and removing transactions
-    pub fn get_by_tx(&self, id: u32) -> Option<&Transaction> {
-        self.inside.iter().find(|tx| tx.id == id)
+    pub fn get_by_tx(&self, id: u32) -> Option<&StoredTransaction> {
+        self.inside.iter().find(|stored| stored.tx.id == id)
+    }
+
+    ///Get a mutable reference to a transaction by its ID from the buffer,
+    ///so that its state can be updated
+    pub fn get_by_tx_mut(&mut self, id: u32) -> Option<&mut StoredTransaction> {
+        self.inside.iter_mut().find(|stored| stored.tx.id == id)
+    }
+}
+
+/// A compact set of transaction IDs, stored as a bitset split into pages
+/// which are only allocated once an ID within their range is inserted
+///
+/// Each page covers 65,536 consecutive IDs in 8KiB, so IDs which are roughly
+/// sequential cost about one bit each, while the full `u32` range tops out at 512MiB
+#[derive(Debug, Default, Clone)]
+pub struct TxIdSet {
+    pages: HashMap<u16, Box<[u64; TxIdSet::WORDS_PER_PAGE]>>,
+}
+
+impl TxIdSet {
+    const WORDS_PER_PAGE: usize = (1 << 16) / 64;
+
+    ///Create a new, empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Splits an ID into its page, the word within the page, and the bit within the word
+    fn locate(id: u32) -> (u16, usize, u64) {
+        let page = (id >> 16) as u16;
+        let offset = (id & 0xFFFF) as usize;
+        (page, offset / 64, 1 << (offset % 64))
+    }
+
+    ///Returns whether the set contains the ID
+    pub fn contains(&self, id: u32) -> bool {
+        let (page, word, bit) = Self::locate(id);
+        self.pages
+            .get(&page)
+            .is_some_and(|words| words[word] & bit != 0)
+    }
+
+    ///Inserts an ID into the set, returning whether it was newly inserted
+    pub fn insert(&mut self, id: u32) -> bool {
+        let (page, word, bit) = Self::locate(id);
+        let words = self
+            .pages
+            .entry(page)
+            .or_insert_with(|| Box::new([0; Self::WORDS_PER_PAGE]));
+        let inserted = words[word] & bit == 0;
+        words[word] |= bit;
+        inserted
+    }
+
+    ///Returns the IDs in the set as sorted, inclusive ranges of consecutive IDs
+    pub fn ranges(&self) -> Vec<(u32, u32)> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_unstable_by_key(|(page, _)| **page);
+
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for (page, words) in pages {
+            let page_start = u32::from(*page) << 16;
+            for (i, word) in words.iter().enumerate() {
+                //Skip over empty words rather than checking each of their bits
+                if *word == 0 {
+                    continue;
+                }
+                for bit in 0..64 {
+                    if word & (1 << bit) == 0 {
+                        continue;
+                    }
+                    let id = page_start + (i * 64 + bit) as u32;
+                    //Extend the last range if this ID follows straight on from it
+                    match ranges.last_mut() {
+                        Some((_, end)) if end.checked_add(1) == Some(id) => *end = id,
+                        _ => ranges.push((id, id)),
+                    }
+                }
+            }
+        }
+        ranges
+    }
+}
+
+///A set is serialized as its ranges of consecutive IDs, since IDs tend to be
+///roughly sequential, which is much smaller than either the pages or every ID
+impl Serialize for TxIdSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.ranges().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TxIdSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ranges = Vec::<(u32, u32)>::deserialize(deserializer)?;
+        let mut set = TxIdSet::new();
+        for (start, end) in ranges {
+            for id in start..=end {
+                set.insert(id);
+            }
+        }
+        Ok(set)
     }
 }
@@ -0,0 +1,353 @@
+use crate::datatypes::{Amount, AmountParseError, Client, Transaction, TxState};
+use std::fmt;
+use std::io;
+
+/// Represents the reasons a transaction record can fail to be processed
+///
+/// Every variant apart from `CsvParse` carries the offending transaction,
+/// which in turn identifies the client involved
+#[derive(Debug)]
+pub enum ProcessError {
+    ///The input row could not be deserialized into a transaction
+    CsvParse(csv::Error),
+    ///The client's account is locked, so it cannot do any transactions
+    LockedAccount { tx: Transaction },
+    ///A deposit or withdrawal didn't include an amount
+    MissingAmount { tx: Transaction },
+    ///A transaction's amount was `NaN` or an infinity. The transaction is
+    ///given without an amount, which is kept as it was written
+    NonFiniteAmount { tx: Transaction, amount: String },
+    ///A transaction's amount had more than 4 decimal places
+    TooPrecise { tx: Transaction, amount: String },
+    ///A transaction's amount couldn't be read as a decimal amount for another reason
+    InvalidAmount {
+        tx: Transaction,
+        error: AmountParseError,
+    },
+    ///A deposit or withdrawal had a zero or negative amount
+    NonPositiveAmount { tx: Transaction },
+    ///A deposit or withdrawal had an amount above the configured maximum
+    AmountTooLarge { tx: Transaction, max: Amount },
+    ///A dispute, resolve, or chargeback had an amount, which only deposits and withdrawals may have
+    UnexpectedAmount { tx: Transaction },
+    ///A deposit or withdrawal reused the ID of a previously processed transaction
+    DuplicateTransaction { tx: Transaction },
+    ///A withdrawal exceeded the client's available funds
+    InsufficientFunds { tx: Transaction },
+    ///Applying the transaction would overflow the client's funds
+    Overflow { tx: Transaction },
+    ///A dispute referenced a transaction that doesn't exist
+    UnknownTransaction { tx: Transaction },
+    ///A dispute referenced a transaction that is no longer retained for dispute lookups
+    EvictedTransaction { tx: Transaction },
+    ///A resolve or chargeback referenced a transaction that isn't disputed
+    UnknownDispute { tx: Transaction },
+    ///A dispute, resolve, or chargeback referenced a client that doesn't exist
+    UnknownClient { tx: Transaction },
+    ///A dispute, resolve, or chargeback was made by a different client than
+    ///the one who made the referenced transaction
+    ClientMismatch { tx: Transaction, owner: u16 },
+    ///A dispute, resolve, or chargeback isn't allowed from the referenced
+    ///transaction's current state, e.g. disputing a transaction twice
+    IllegalTransition { tx: Transaction, state: TxState },
+    ///A dispute referenced a transaction that can't be disputed
+    NotDisputable { tx: Transaction },
+    ///The transaction store couldn't be read from or written to
+    Storage { tx: Transaction, source: io::Error },
+}
+
+/// The broad kinds of `ProcessError`, for callers which handle every error
+/// of a kind the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    ///The row couldn't be deserialized into a transaction
+    Parse,
+    ///The transaction was malformed, e.g. a deposit without an amount
+    Validation,
+    ///The transaction was well formed, but not allowed given the state of the ledger
+    Rule,
+    ///The transaction store failed
+    Storage,
+}
+
+impl ProcessError {
+    ///Returns a short, stable code identifying the kind of error, which unlike the
+    ///message is safe for other tools to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessError::CsvParse(_) => "parse_error",
+            ProcessError::LockedAccount { .. } => "locked_account",
+            ProcessError::MissingAmount { .. } => "missing_amount",
+            ProcessError::NonFiniteAmount { .. } => "non_finite_amount",
+            ProcessError::TooPrecise { .. } => "too_precise_amount",
+            ProcessError::InvalidAmount { .. } => "invalid_amount",
+            ProcessError::NonPositiveAmount { .. } => "non_positive_amount",
+            ProcessError::AmountTooLarge { .. } => "amount_too_large",
+            ProcessError::UnexpectedAmount { .. } => "unexpected_amount",
+            ProcessError::DuplicateTransaction { .. } => "duplicate_transaction",
+            ProcessError::InsufficientFunds { .. } => "insufficient_funds",
+            ProcessError::Overflow { .. } => "overflow",
+            ProcessError::UnknownTransaction { .. } => "unknown_transaction",
+            ProcessError::EvictedTransaction { .. } => "evicted_transaction",
+            ProcessError::UnknownDispute { .. } => "unknown_dispute",
+            ProcessError::UnknownClient { .. } => "unknown_client",
+            ProcessError::ClientMismatch { .. } => "client_mismatch",
+            ProcessError::IllegalTransition { .. } => "illegal_transition",
+            ProcessError::NotDisputable { .. } => "not_disputable",
+            ProcessError::Storage { .. } => "storage_error",
+        }
+    }
+
+    ///Returns the category of the error
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ProcessError::CsvParse(_) => ErrorCategory::Parse,
+            ProcessError::MissingAmount { .. }
+            | ProcessError::NonFiniteAmount { .. }
+            | ProcessError::TooPrecise { .. }
+            | ProcessError::InvalidAmount { .. }
+            | ProcessError::NonPositiveAmount { .. }
+            | ProcessError::AmountTooLarge { .. }
+            | ProcessError::UnexpectedAmount { .. } => ErrorCategory::Validation,
+            ProcessError::Storage { .. } => ErrorCategory::Storage,
+            _ => ErrorCategory::Rule,
+        }
+    }
+
+    ///Returns the offending transaction, which every error apart from `CsvParse` has
+    pub fn transaction(&self) -> Option<&Transaction> {
+        match self {
+            ProcessError::CsvParse(_) => None,
+            ProcessError::LockedAccount { tx }
+            | ProcessError::MissingAmount { tx }
+            | ProcessError::NonFiniteAmount { tx, .. }
+            | ProcessError::TooPrecise { tx, .. }
+            | ProcessError::InvalidAmount { tx, .. }
+            | ProcessError::NonPositiveAmount { tx }
+            | ProcessError::AmountTooLarge { tx, .. }
+            | ProcessError::UnexpectedAmount { tx }
+            | ProcessError::DuplicateTransaction { tx }
+            | ProcessError::InsufficientFunds { tx }
+            | ProcessError::Overflow { tx }
+            | ProcessError::UnknownTransaction { tx }
+            | ProcessError::EvictedTransaction { tx }
+            | ProcessError::UnknownDispute { tx }
+            | ProcessError::UnknownClient { tx }
+            | ProcessError::ClientMismatch { tx, .. }
+            | ProcessError::IllegalTransition { tx, .. }
+            | ProcessError::NotDisputable { tx }
+            | ProcessError::Storage { tx, .. } => Some(tx),
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::CsvParse(e) => write!(f, "Could not parse transaction: {e}"),
+            ProcessError::LockedAccount { tx } => write!(f, "Client is locked: {tx:?}"),
+            ProcessError::MissingAmount { tx } => {
+                write!(f, "{:?} transaction missing amount: {tx:?}", tx.tx_type)
+            }
+            ProcessError::NonFiniteAmount { tx, amount } => {
+                write!(
+                    f,
+                    "{:?} amount {amount:?} is not finite: {tx:?}",
+                    tx.tx_type
+                )
+            }
+            ProcessError::TooPrecise { tx, amount } => write!(
+                f,
+                "{:?} amount {amount:?} has more than 4 decimal places: {tx:?}",
+                tx.tx_type
+            ),
+            ProcessError::InvalidAmount { tx, error } => {
+                write!(f, "{:?} has an {error}: {tx:?}", tx.tx_type)
+            }
+            ProcessError::NonPositiveAmount { tx } => {
+                write!(f, "{:?} amount must be positive: {tx:?}", tx.tx_type)
+            }
+            ProcessError::AmountTooLarge { tx, max } => {
+                write!(
+                    f,
+                    "{:?} amount exceeds maximum of {max}: {tx:?}",
+                    tx.tx_type
+                )
+            }
+            ProcessError::UnexpectedAmount { tx } => {
+                write!(f, "{:?} must not have an amount: {tx:?}", tx.tx_type)
+            }
+            ProcessError::DuplicateTransaction { tx } => {
+                write!(f, "Duplicate transaction ID: {tx:?}")
+            }
+            ProcessError::InsufficientFunds { tx } => {
+                write!(f, "Insufficient funds for withdrawal: {tx:?}")
+            }
+            ProcessError::Overflow { tx } => {
+                write!(f, "{:?} would overflow client funds: {tx:?}", tx.tx_type)
+            }
+            ProcessError::UnknownTransaction { tx } => {
+                write!(f, "Dispute references non-existent transaction: {tx:?}")
+            }
+            ProcessError::EvictedTransaction { tx } => write!(
+                f,
+                "Dispute references transaction which is no longer retained: {tx:?}"
+            ),
+            ProcessError::UnknownDispute { tx } => {
+                write!(
+                    f,
+                    "{:?} references non-existent dispute: {tx:?}",
+                    tx.tx_type
+                )
+            }
+            ProcessError::UnknownClient { tx } => {
+                write!(f, "{:?} references non-existent client: {tx:?}", tx.tx_type)
+            }
+            ProcessError::ClientMismatch { tx, owner } => write!(
+                f,
+                "{:?} references transaction of client {owner}: {tx:?}",
+                tx.tx_type
+            ),
+            ProcessError::IllegalTransition { tx, state } => write!(
+                f,
+                "{:?} not allowed on a transaction which is {state:?}: {tx:?}",
+                tx.tx_type
+            ),
+            ProcessError::NotDisputable { tx } => write!(
+                f,
+                "Dispute references transaction which can't be disputed: {tx:?}"
+            ),
+            ProcessError::Storage { tx, source } => {
+                write!(f, "Transaction store error ({source}): {tx:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProcessError::CsvParse(e) => Some(e),
+            ProcessError::InvalidAmount { error, .. } => Some(error),
+            ProcessError::Storage { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<csv::Error> for ProcessError {
+    fn from(e: csv::Error) -> Self {
+        ProcessError::CsvParse(e)
+    }
+}
+
+/// Represents the reasons a snapshot of client records can fail to be loaded
+#[derive(Debug)]
+pub enum SnapshotError {
+    ///A snapshot row could not be deserialized into a client record
+    CsvParse(csv::Error),
+    ///A client's available and held funds don't add up to its total
+    Unbalanced { client: Client },
+    ///A client was already in the ledger, e.g. because the snapshot has two records for it
+    DuplicateClient { client: u16 },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::CsvParse(e) => write!(f, "Could not parse client record: {e}"),
+            SnapshotError::Unbalanced { client } => write!(
+                f,
+                "Client {} has available {} and held {}, which don't add up to total {}",
+                client.client, client.available, client.held, client.total
+            ),
+            SnapshotError::DuplicateClient { client } => {
+                write!(f, "Client {client} appears more than once")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::CsvParse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<csv::Error> for SnapshotError {
+    fn from(e: csv::Error) -> Self {
+        SnapshotError::CsvParse(e)
+    }
+}
+
+/// Represents the reasons a checkpoint can fail to be saved or restored
+#[derive(Debug)]
+pub enum CheckpointError {
+    ///The checkpoint file or transaction store couldn't be read from or written to
+    Io(io::Error),
+    ///The checkpoint file isn't a valid checkpoint
+    Format(serde_json::Error),
+    ///The checkpoint was written in a format version this build doesn't support
+    UnsupportedVersion { version: u64 },
+    ///The checkpoint's transaction history is kept in a store which persists it,
+    ///such as a `DiskStore`, but it was restored onto a store kept in memory
+    ExternalHistoryMissing,
+    ///The checkpoint has its own transaction history, but it was restored onto a
+    ///store which persists its own, such as a `DiskStore`
+    ExternalHistoryUnexpected,
+    ///The store's persisted history is shorter than the length recorded in the
+    ///checkpoint, so it isn't the history the checkpoint was taken with
+    HistoryMismatch { expected: u64, found: u64 },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "Checkpoint I/O error: {e}"),
+            CheckpointError::Format(e) => write!(f, "Invalid checkpoint: {e}"),
+            CheckpointError::UnsupportedVersion { version } => {
+                write!(f, "Unsupported checkpoint version {version}")
+            }
+            CheckpointError::ExternalHistoryMissing => write!(
+                f,
+                "Checkpoint's transaction history is kept in a separate store, \
+                 such as a history directory, which wasn't given"
+            ),
+            CheckpointError::ExternalHistoryUnexpected => write!(
+                f,
+                "Checkpoint has its own transaction history, so it can't be restored \
+                 onto a separate store such as a history directory"
+            ),
+            CheckpointError::HistoryMismatch { expected, found } => write!(
+                f,
+                "Transaction history has length {found} but the checkpoint was taken \
+                 with length {expected}, so it isn't the same history"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(e) => Some(e),
+            CheckpointError::Format(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CheckpointError {
+    fn from(e: serde_json::Error) -> Self {
+        CheckpointError::Format(e)
+    }
+}
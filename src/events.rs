@@ -0,0 +1,51 @@
+use serde::Serialize;
+use transaction_processor::datatypes::{Amount, Client, TransactionType};
+
+/// A change to a client's balances made by an applied transaction
+#[derive(Serialize)]
+pub struct BalanceEvent {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    ///How much each balance changed by
+    pub available_delta: Amount,
+    pub held_delta: Amount,
+    pub total_delta: Amount,
+    ///The balances after the transaction
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    ///Whether this transaction locked the account
+    pub became_locked: bool,
+}
+
+impl BalanceEvent {
+    ///Describe the change from `before` to `after` made by a transaction, where
+    ///`before` is `None` if the transaction created the client's record
+    pub fn new(tx: u32, tx_type: TransactionType, before: Option<Client>, after: &Client) -> Self {
+        let before = before.unwrap_or_else(|| Client::new(after.client));
+        Self {
+            client: after.client,
+            tx,
+            tx_type,
+            available_delta: delta(before.available, after.available),
+            held_delta: delta(before.held, after.held),
+            total_delta: delta(before.total, after.total),
+            available: after.available,
+            held: after.held,
+            total: after.total,
+            locked: after.locked,
+            became_locked: after.locked && !before.locked,
+        }
+    }
+}
+
+///The change from one balance to another
+fn delta(before: Amount, after: Amount) -> Amount {
+    //A single transaction moves a balance by at most its own amount, which fits
+    after
+        .checked_sub(before)
+        .expect("balance changed by more than an amount can hold")
+}
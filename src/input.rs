@@ -0,0 +1,176 @@
+use csv::{Reader, ReaderBuilder, StringRecord};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use transaction_processor::datatypes::Transaction;
+use transaction_processor::error::ProcessError;
+use transaction_processor::validate;
+
+/// A CSV of transactions being read from a file or stdin
+pub struct Input {
+    ///The name errors from this input are attributed to
+    pub name: String,
+    reader: Reader<Box<dyn Read>>,
+    headers: StringRecord,
+    ///Set once reading fails in a way that can't be recovered from
+    done: bool,
+}
+
+/// A row read from an input
+pub struct Row {
+    ///The fields of the row, which may be incomplete if the row itself couldn't be read
+    pub record: StringRecord,
+    ///The line the row starts on
+    pub line: u64,
+    ///The byte offset the row starts at
+    pub byte: u64,
+}
+
+/// An error from a row, which displays the input, line, and byte offset it
+/// came from in front of the error itself
+pub struct RowError<'a> {
+    pub input: &'a Input,
+    pub row: &'a Row,
+    pub error: &'a ProcessError,
+}
+
+impl fmt::Display for RowError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} (byte {}): {}",
+            self.input.name,
+            self.row.line,
+            self.row.byte,
+            ErrorReason(self.error)
+        )
+    }
+}
+
+/// Displays an error from a row without the position the CSV reader puts in
+/// its own errors, for when the position is given separately
+pub struct ErrorReason<'a>(pub &'a ProcessError);
+
+impl fmt::Display for ErrorReason<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ProcessError::CsvParse(e) => {
+                write!(f, "Could not parse transaction: ")?;
+                write_csv_error(f, e)
+            }
+            error => write!(f, "{error}"),
+        }
+    }
+}
+
+///Write a CSV error the way the CSV reader would, but without the position of the record
+fn write_csv_error(f: &mut fmt::Formatter<'_>, e: &csv::Error) -> fmt::Result {
+    match e.kind() {
+        //The error already says which field it was in
+        csv::ErrorKind::Utf8 { err, .. } => write!(f, "CSV parse error: {err}"),
+        csv::ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => write!(
+            f,
+            "CSV error: found record with {len} fields, but the previous record has \
+             {expected_len} fields"
+        ),
+        csv::ErrorKind::Deserialize { err, .. } => write!(f, "CSV deserialize error: {err}"),
+        //Nothing else has a position
+        _ => write!(f, "{e}"),
+    }
+}
+
+impl Input {
+    ///Open a CSV of transactions for reading, where `-` is stdin
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let name = if path == Path::new("-") {
+            "<stdin>".to_string()
+        } else {
+            path.display().to_string()
+        };
+
+        //Use a buffered reader to read the input file to avoid
+        //making a system call for each iteration of the main loop.
+        //Stdin is already buffered
+        let input_buf: Box<dyn Read> = if path == Path::new("-") {
+            Box::new(io::stdin().lock())
+        } else {
+            let input_file = File::open(path).map_err(|e| format!("could not open {name}: {e}"))?;
+            Box::new(BufReader::new(input_file))
+        };
+
+        //configure csv reader
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .delimiter(b',')
+            .from_reader(input_buf);
+
+        //The headers are kept so rows can be deserialized after they're read
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("could not read the header of {name}: {e}"))?
+            .clone();
+
+        Ok(Self {
+            name,
+            reader,
+            headers,
+            done: false,
+        })
+    }
+
+    ///Returns the raw value of a column of a row, if the input has that column
+    pub fn field<'r>(&self, row: &'r Row, column: &str) -> Option<&'r str> {
+        let index = self.headers.iter().position(|header| header == column)?;
+        row.record.get(index)
+    }
+
+    ///Returns the client and transaction ID of a row. They're taken from its
+    ///transaction if it got that far, otherwise they're picked out of the row,
+    ///since a row can fail to parse for other reasons
+    pub fn ids(&self, row: &Row, tx: Option<&Transaction>) -> (Option<u16>, Option<u32>) {
+        match tx {
+            Some(tx) => (Some(tx.client), Some(tx.id)),
+            None => (
+                self.field(row, "client")
+                    .and_then(|client| client.parse().ok()),
+                self.field(row, "tx").and_then(|tx| tx.parse().ok()),
+            ),
+        }
+    }
+
+    ///Read the next row and deserialize it into a transaction, keeping the row
+    ///itself so that it can be reported if the transaction is rejected
+    pub fn next_row(&mut self) -> Option<(Row, Result<Transaction, ProcessError>)> {
+        if self.done {
+            return None;
+        }
+
+        let mut record = StringRecord::new();
+        match self.reader.read_record(&mut record) {
+            Ok(false) => None,
+            Ok(true) => {
+                let (line, byte) = record
+                    .position()
+                    .map_or((0, 0), |position| (position.line(), position.byte()));
+                //The amount is parsed separately, so an invalid amount is a
+                //validation error rather than an unreadable row
+                let result = record
+                    .deserialize(Some(&self.headers))
+                    .map_err(ProcessError::from)
+                    .and_then(validate::parse);
+                Some((Row { record, line, byte }, result))
+            }
+            Err(e) => {
+                //Malformed rows are skipped, but an I/O error would just repeat
+                self.done = e.is_io_error();
+                let (line, byte) = e
+                    .position()
+                    .map_or((0, 0), |position| (position.line(), position.byte()));
+                Some((Row { record, line, byte }, Err(ProcessError::from(e))))
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::datatypes::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// An append-only journal of every transaction a `Ledger` accepted, along with
+/// where in the input each one came from
+///
+/// If a run dies part way through, replaying the journal on top of the checkpoint
+/// the run started from rebuilds the state it had reached, and the position of the
+/// last entry says where in the input to carry on from.
+///
+/// Entries are JSON lines, and are synced to disk in batches of `sync_interval`.
+/// Entries written since the last sync may be lost in a crash, which only moves
+/// the position to carry on from back, since the state is rebuilt from the
+/// entries which did make it to disk. A final line which was only partially
+/// written is ignored when the journal is read, and dropped when it's opened
+/// to be appended to.
+pub struct Journal {
+    file: BufWriter<File>,
+    ///How many entries to write between syncs
+    sync_interval: usize,
+    ///How many entries have been written since the last sync
+    unsynced: usize,
+}
+
+/// A transaction read back from a journal
+#[derive(Debug, Deserialize)]
+pub struct JournalEntry {
+    ///The name of the input the transaction was read from
+    pub input: String,
+    ///The line and byte offset of the row the transaction was read from
+    pub line: u64,
+    pub byte: u64,
+    pub tx: Transaction,
+}
+
+/// The borrowed form of `JournalEntry`, so that appending doesn't need to copy anything
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    input: &'a str,
+    line: u64,
+    byte: u64,
+    tx: &'a Transaction,
+}
+
+impl Journal {
+    ///Open the journal at `path` for appending, creating it if it doesn't exist
+    pub fn open(path: &Path, sync_interval: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        //A line cut off by a crash would otherwise run into the first new entry
+        let (_, len) = read_entries(&file)?;
+        file.set_len(len)?;
+        Ok(Self {
+            file: BufWriter::new(file),
+            sync_interval: sync_interval.max(1),
+            unsynced: 0,
+        })
+    }
+
+    ///Append an accepted transaction read from the row at `line` and `byte` of `input`
+    pub fn append(
+        &mut self,
+        input: &str,
+        line: u64,
+        byte: u64,
+        tx: &Transaction,
+    ) -> io::Result<()> {
+        let entry = EntryRef {
+            input,
+            line,
+            byte,
+            tx,
+        };
+        serde_json::to_writer(&mut self.file, &entry)?;
+        self.file.write_all(b"\n")?;
+
+        self.unsynced += 1;
+        if self.unsynced >= self.sync_interval {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    ///Write out any buffered entries and sync them to disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    ///Read every entry in the journal at `path`, oldest first. A journal which
+    ///doesn't exist has no entries
+    pub fn read(path: &Path) -> io::Result<Vec<JournalEntry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let (entries, _) = read_entries(&file)?;
+        Ok(entries)
+    }
+
+    ///Empty the journal at `path`, once the state it records has been saved elsewhere
+    pub fn clear(path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        file.sync_all()
+    }
+}
+
+///Read every complete entry in a journal file, along with the length of the lines
+///they were read from, which leaves out a final line cut off by a crash
+fn read_entries(file: &File) -> io::Result<(Vec<JournalEntry>, u64)> {
+    let mut entries = Vec::new();
+    let mut len = 0;
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        //Only the last line can have been cut off, and an entry isn't complete
+        //until the newline after it is written
+        if !line.ends_with('\n') {
+            break;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+        len += line.len() as u64;
+        line.clear();
+    }
+    Ok((entries, len))
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        //Errors can't be reported from drop, call sync() first to handle them
+        let _ = self.sync();
+    }
+}
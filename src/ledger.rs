@@ -0,0 +1,740 @@
+use crate::checkpoint::{Checkpoint, History};
+use crate::datatypes::{
+    Amount, Client, StoredTransaction, Transaction, TransactionType, TxIdSet, TxState,
+};
+use crate::error::{CheckpointError, ProcessError, SnapshotError};
+use crate::store::{IndexedBuffer, Retention, TransactionStore};
+use crate::validate::validate;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Determines how far back deposits and withdrawals are checked for a reused transaction ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupPolicy {
+    ///Only reject IDs of transactions which are still retained for dispute lookups.
+    ///This needs no extra memory, but an ID can be reused once it has been evicted
+    Window,
+    ///Reject any ID which has ever been processed, tracked in a compact `TxIdSet`
+    #[default]
+    AllTime,
+}
+
+/// Determines whether withdrawals can be disputed, and how a disputed withdrawal
+/// affects the client's funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputePolicy {
+    ///Only deposits can be disputed, disputes of withdrawals are rejected
+    DepositsOnly,
+    ///Withdrawals are disputed exactly like deposits: the amount moves from
+    ///available to held, and a chargeback removes it from held and total
+    #[default]
+    DepositSemantics,
+    ///A disputed withdrawal may be reversed, so its amount is held back as a potential
+    ///credit: held and total increase while available is unchanged. A resolve lets the
+    ///withdrawal stand and removes the hold, while a chargeback reverses the withdrawal
+    ///by releasing the held amount back into available
+    ReverseWithdrawals,
+}
+
+impl DisputePolicy {
+    ///Returns whether a dispute of a transaction with the given type uses reversed-sign
+    ///withdrawal semantics instead of deposit semantics
+    fn reverses(self, disputed: &TransactionType) -> bool {
+        self == DisputePolicy::ReverseWithdrawals && *disputed == TransactionType::Withdrawal
+    }
+}
+
+/// Determines the order client records are output in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputOrder {
+    ///By client ID, ascending
+    #[default]
+    ClientId,
+    ///By total funds, descending, with ties broken by client ID
+    TotalDesc,
+    ///In the order clients first appeared in the input
+    FirstSeen,
+}
+
+impl FromStr for OutputOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(OutputOrder::ClientId),
+            "total" => Ok(OutputOrder::TotalDesc),
+            "first-seen" => Ok(OutputOrder::FirstSeen),
+            _ => Err(format!(
+                "invalid order {s:?}, expected `id`, `total`, or `first-seen`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputOrder::ClientId => "id",
+            OutputOrder::TotalDesc => "total",
+            OutputOrder::FirstSeen => "first-seen",
+        })
+    }
+}
+
+/// Configures how a `Ledger` processes transactions
+#[derive(Debug, Clone, Default)]
+pub struct LedgerConfig {
+    ///How long processed transactions are kept for dispute lookups
+    pub retention: Retention,
+    ///How far back deposits and withdrawals are checked for duplicate IDs
+    pub dedup: DedupPolicy,
+    ///The largest amount a deposit or withdrawal may have, if any
+    pub max_amount: Option<Amount>,
+    ///How disputes of withdrawals are handled
+    pub disputes: DisputePolicy,
+}
+
+/// Owns the state of every client along with the transactions needed to
+/// process disputes, and applies transactions to that state one at a time
+pub struct Ledger {
+    clients: HashMap<u16, Client>,
+    ///Client IDs in the order the clients were created
+    client_order: Vec<u16>,
+    processed_txs: Box<dyn TransactionStore>,
+    held_txs: HashMap<u32, Transaction>,
+    seen_txs: TxIdSet,
+    ///IDs of transactions which have been evicted from `processed_txs`
+    evicted_txs: TxIdSet,
+    ///The number of disputes which failed because their transaction was evicted
+    evicted_disputes: u64,
+    config: LedgerConfig,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ledger {
+    ///Create a new, empty `Ledger` which keeps the default number of
+    ///processed transactions for dispute lookups
+    pub fn new() -> Self {
+        Self::with_config(LedgerConfig::default())
+    }
+
+    ///Create a new, empty `Ledger` which keeps up to `capacity` processed
+    ///transactions for dispute lookups
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_config(LedgerConfig {
+            retention: Retention::Count(capacity),
+            ..LedgerConfig::default()
+        })
+    }
+
+    ///Create a new, empty `Ledger` with the given configuration
+    pub fn with_config(config: LedgerConfig) -> Self {
+        let store = IndexedBuffer::with_retention(config.retention);
+        Self::with_store(config, store)
+    }
+
+    ///Create a new, empty `Ledger` which keeps processed transactions for dispute
+    ///lookups in `store`, such as a `DiskStore`, instead of in memory. The store
+    ///decides which transactions are retained, so `config.retention` is ignored
+    pub fn with_store(config: LedgerConfig, store: impl TransactionStore + 'static) -> Self {
+        Self {
+            clients: HashMap::new(),
+            client_order: Vec::new(),
+            processed_txs: Box::new(store),
+            held_txs: HashMap::new(),
+            seen_txs: TxIdSet::new(),
+            evicted_txs: TxIdSet::new(),
+            evicted_disputes: 0,
+            config,
+        }
+    }
+
+    ///Add a client record from an earlier run, e.g. an opening balance
+    ///
+    ///The client must not already be in the ledger, and its available and
+    ///held funds must add up to its total
+    pub fn load_client(&mut self, client: Client) -> Result<(), SnapshotError> {
+        if client.available.checked_add(client.held) != Some(client.total) {
+            return Err(SnapshotError::Unbalanced { client });
+        }
+
+        match self.clients.entry(client.client) {
+            Entry::Occupied(_) => Err(SnapshotError::DuplicateClient {
+                client: client.client,
+            }),
+            Entry::Vacant(entry) => {
+                self.client_order.push(client.client);
+                entry.insert(client);
+                Ok(())
+            }
+        }
+    }
+
+    ///Load a snapshot of client records in the same CSV format they're written out in,
+    ///returning how many were loaded. This lets a run continue on from the balances
+    ///at the end of an earlier one
+    pub fn load_snapshot(&mut self, reader: impl io::Read) -> Result<usize, SnapshotError> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        let mut loaded = 0;
+        for client in csv_reader.deserialize::<Client>() {
+            self.load_client(client?)?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    ///Capture the entire state of the ledger, so it can be restored later with `restore`
+    pub fn checkpoint(&self) -> Checkpoint {
+        let clients = self
+            .client_order
+            .iter()
+            .filter_map(|id| self.clients.get(id))
+            .cloned()
+            .collect();
+
+        //Sort the disputes so the same state always produces the same checkpoint
+        let mut disputes: Vec<Transaction> = self.held_txs.values().cloned().collect();
+        disputes.sort_unstable_by_key(|tx| tx.id);
+
+        //Stores which persist their transactions only need to check they're
+        //restored onto the same history
+        let history = match self.processed_txs.retained() {
+            Some(retained) => History::Retained(retained),
+            None => History::External {
+                len: self.processed_txs.persisted_len().unwrap_or(0),
+            },
+        };
+
+        Checkpoint::new(
+            clients,
+            history,
+            disputes,
+            self.seen_txs.clone(),
+            self.evicted_txs.clone(),
+            self.evicted_disputes,
+        )
+    }
+
+    ///Replace the state of the ledger with a checkpoint taken by `checkpoint`
+    ///
+    ///A checkpoint with its own history replaces this ledger's transaction store with
+    ///an in-memory one holding just the retained transactions in the checkpoint, so
+    ///any which fall outside `config.retention` are evicted.
+    ///If the checkpoint was taken from a ledger whose store persists its own
+    ///transactions, such as a `DiskStore`, this ledger must be given the same
+    ///store, reopened, and the checkpoint is rejected if the store doesn't hold
+    ///the same history. Anything the store persisted after the checkpoint was
+    ///taken is rolled back. Likewise a checkpoint with its own history is rejected by
+    ///a store which persists its own
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), CheckpointError> {
+        //Check the history before changing anything, so a rejected checkpoint
+        //leaves the ledger as it was
+        let retained = match (checkpoint.history, self.processed_txs.persisted_len()) {
+            (History::Retained(retained), None) => Some(retained),
+            (History::External { len }, Some(found)) => {
+                if found < len {
+                    return Err(CheckpointError::HistoryMismatch {
+                        expected: len,
+                        found,
+                    });
+                }
+                //Anything stored after the checkpoint was taken, e.g. by a run which
+                //didn't finish, isn't part of its state, so is discarded
+                if found > len {
+                    self.processed_txs.rollback(len)?;
+                }
+                None
+            }
+            (History::External { .. }, None) => {
+                return Err(CheckpointError::ExternalHistoryMissing)
+            }
+            (History::Retained(_), Some(_)) => {
+                return Err(CheckpointError::ExternalHistoryUnexpected)
+            }
+        };
+
+        self.clients.clear();
+        self.client_order.clear();
+        for client in checkpoint.clients {
+            self.client_order.push(client.client);
+            self.clients.insert(client.client, client);
+        }
+
+        //Transactions processed before restoring aren't part of the checkpoint's state
+        if retained.is_some() {
+            self.processed_txs = Box::new(IndexedBuffer::with_retention(self.config.retention));
+        }
+        self.evicted_txs = checkpoint.evicted;
+        for stored in retained.into_iter().flatten() {
+            if let Some(evicted) = self.processed_txs.push(stored)? {
+                self.evicted_txs.insert(evicted.tx.id);
+            }
+        }
+
+        self.held_txs = checkpoint
+            .disputes
+            .into_iter()
+            .map(|tx| (tx.id, tx))
+            .collect();
+        self.seen_txs = checkpoint.seen;
+        self.evicted_disputes = checkpoint.evicted_disputes;
+        Ok(())
+    }
+
+    ///Returns an iterator over all client records, in no particular order
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    ///Returns all client records in the given order
+    pub fn clients_ordered(&self, order: OutputOrder) -> Vec<&Client> {
+        match order {
+            OutputOrder::ClientId => {
+                let mut clients: Vec<&Client> = self.clients.values().collect();
+                clients.sort_unstable_by_key(|client| client.client);
+                clients
+            }
+            OutputOrder::TotalDesc => {
+                let mut clients: Vec<&Client> = self.clients.values().collect();
+                clients.sort_unstable_by_key(|client| (Reverse(client.total), client.client));
+                clients
+            }
+            OutputOrder::FirstSeen => self
+                .client_order
+                .iter()
+                .filter_map(|id| self.clients.get(id))
+                .collect(),
+        }
+    }
+
+    ///Returns the record of a single client, if it exists
+    pub fn client(&self, client: u16) -> Option<&Client> {
+        self.clients.get(&client)
+    }
+
+    ///Returns a processed deposit or withdrawal by its ID, if it's still
+    ///retained for dispute lookups
+    ///
+    ///An error reading the transaction store is treated as the transaction not
+    ///being found, whereas `apply` reports it as a `ProcessError::Storage`
+    pub fn transaction(&self, id: u32) -> Option<Transaction> {
+        let stored = self.processed_txs.get(id).ok().flatten();
+        stored.map(|stored| stored.tx)
+    }
+
+    ///Returns the lifecycle state of a processed deposit or withdrawal, if it's
+    ///still retained for dispute lookups
+    pub fn state(&self, id: u32) -> Option<TxState> {
+        let stored = self.processed_txs.get(id).ok().flatten();
+        stored.map(|stored| stored.state)
+    }
+
+    ///Writes out anything the transaction store has buffered
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.processed_txs.flush()
+    }
+
+    ///Returns the number of disputes which failed because the transaction they referenced
+    ///had already been evicted, which suggests the retention should be increased
+    pub fn evicted_dispute_failures(&self) -> u64 {
+        self.evicted_disputes
+    }
+
+    ///Returns the number of transactions which are currently under dispute
+    pub fn open_disputes(&self) -> usize {
+        self.held_txs.len()
+    }
+
+    ///Returns the disputed transaction with the given ID, if it's currently under dispute
+    pub fn dispute(&self, id: u32) -> Option<&Transaction> {
+        self.held_txs.get(&id)
+    }
+
+    /// Processes a transaction record and updates the client, processed transactions,
+    /// and held transactions state accordingly
+    ///
+    /// Errors are returned as a `ProcessError` describing why the transaction was rejected
+    pub fn apply(&mut self, tx: Transaction) -> Result<(), ProcessError> {
+        //Evict transactions which have outlived an age based retention
+        if let Retention::Age(_) = self.config.retention {
+            let now = Instant::now();
+            while let Some(evicted) = self.processed_txs.pop_expired(now) {
+                self.evicted_txs.insert(evicted.tx.id);
+            }
+        }
+
+        let Ledger {
+            clients,
+            client_order,
+            processed_txs,
+            held_txs,
+            seen_txs,
+            evicted_txs,
+            evicted_disputes,
+            config,
+        } = self;
+
+        //Reject malformed transactions before they can affect any state
+        let tx = validate(tx, config.max_amount)?;
+
+        //Deposits and withdrawals are referred back to by their ID, so an
+        //ID which has already been processed can't be used again
+        if matches!(
+            tx.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            let duplicate = match config.dedup {
+                //A disputed transaction is still referred back to by its ID even
+                //once it's been evicted, so it counts as a duplicate until settled
+                DedupPolicy::Window => match processed_txs.get(tx.id) {
+                    Ok(stored) => stored.is_some() || held_txs.contains_key(&tx.id),
+                    Err(source) => return Err(ProcessError::Storage { tx, source }),
+                },
+                DedupPolicy::AllTime => seen_txs.contains(tx.id),
+            };
+            if duplicate {
+                return Err(ProcessError::DuplicateTransaction { tx });
+            }
+        }
+
+        match tx.tx_type {
+            TransactionType::Deposit => {
+                //Get the client record from the hashmap, or create a new one
+                let client = clients.entry(tx.client).or_insert_with(|| {
+                    client_order.push(tx.client);
+                    Client::new(tx.client)
+                });
+
+                //A client who's account is frozen cannot do any transactions
+                if client.locked {
+                    return Err(ProcessError::LockedAccount { tx });
+                }
+
+                //Unwrap the amount or return an error if it doesn't exist
+                let amount = tx
+                    .amount
+                    .ok_or_else(|| ProcessError::MissingAmount { tx: tx.clone() })?;
+
+                //Compute the client's new available and total funds before
+                //updating either, so an overflow leaves the client untouched
+                let available = client.available.checked_add(amount);
+                let total = client.total.checked_add(amount);
+                let (Some(available), Some(total)) = (available, total) else {
+                    return Err(ProcessError::Overflow { tx });
+                };
+
+                //push the processed transaction into the store for future reference
+                //if needed. This is done before updating the client so that a
+                //storage error leaves the client untouched
+                let stored = StoredTransaction::new(tx.clone());
+                match processed_txs.push(stored) {
+                    Ok(Some(evicted)) => evicted_txs.insert(evicted.tx.id),
+                    Ok(None) => false,
+                    Err(source) => return Err(ProcessError::Storage { tx, source }),
+                };
+
+                //increment the client's available and total funds
+                client.available = available;
+                client.total = total;
+
+                //Remember the transaction's ID if duplicates are checked for all time
+                if config.dedup == DedupPolicy::AllTime {
+                    seen_txs.insert(tx.id);
+                }
+            }
+            TransactionType::Withdrawal => {
+                //Get the client record from the hashmap, or create a new one
+                let client = clients.entry(tx.client).or_insert_with(|| {
+                    client_order.push(tx.client);
+                    Client::new(tx.client)
+                });
+
+                //A client who's account is frozen cannot do any transactions
+                if client.locked {
+                    return Err(ProcessError::LockedAccount { tx });
+                }
+
+                //Unwrap the amount or return an error if it doesn't exist
+                let amount = tx
+                    .amount
+                    .ok_or_else(|| ProcessError::MissingAmount { tx: tx.clone() })?;
+
+                //Check if the client has enough funds to withdraw.
+                //This will also catch a new client trying to withdraw
+                //before depositing, but perhaps that should be a separate error ?
+                if client.available < amount {
+                    return Err(ProcessError::InsufficientFunds { tx });
+                }
+
+                //Compute the client's new available and total funds before
+                //updating either, so an overflow leaves the client untouched
+                let available = client.available.checked_sub(amount);
+                let total = client.total.checked_sub(amount);
+                let (Some(available), Some(total)) = (available, total) else {
+                    return Err(ProcessError::Overflow { tx });
+                };
+
+                //Push the processed transaction into the store for future reference
+                //if needed. This is done before updating the client so that a
+                //storage error leaves the client untouched
+                let stored = StoredTransaction::new(tx.clone());
+                match processed_txs.push(stored) {
+                    Ok(Some(evicted)) => evicted_txs.insert(evicted.tx.id),
+                    Ok(None) => false,
+                    Err(source) => return Err(ProcessError::Storage { tx, source }),
+                };
+
+                //Decrement the client's available and total funds
+                client.available = available;
+                client.total = total;
+
+                //Remember the transaction's ID if duplicates are checked for all time
+                if config.dedup == DedupPolicy::AllTime {
+                    seen_txs.insert(tx.id);
+                }
+            }
+            TransactionType::Dispute => {
+                //Lookup the transaction referenced by the dispute, distinguishing
+                //transactions which were evicted from ones which never existed
+                let stored = match processed_txs.get(tx.id) {
+                    Ok(Some(stored)) => stored,
+                    Ok(None) if evicted_txs.contains(tx.id) => {
+                        *evicted_disputes += 1;
+                        return Err(ProcessError::EvictedTransaction { tx });
+                    }
+                    Ok(None) => return Err(ProcessError::UnknownTransaction { tx }),
+                    Err(source) => return Err(ProcessError::Storage { tx, source }),
+                };
+                let disputed_tx = &stored.tx;
+
+                //Only the client who made the transaction can dispute it
+                if disputed_tx.client != tx.client {
+                    let owner = disputed_tx.client;
+                    return Err(ProcessError::ClientMismatch { tx, owner });
+                }
+
+                //A transaction can only be disputed once, so it must not have
+                //been disputed, resolved, or charged back already
+                let Some(next_state) = stored.state.transition(&tx.tx_type) else {
+                    let state = stored.state;
+                    return Err(ProcessError::IllegalTransition { tx, state });
+                };
+
+                //Get the client record from the hashmap. This should always exist
+                //but check error just for safety
+                let client = clients
+                    .get_mut(&disputed_tx.client)
+                    .ok_or_else(|| ProcessError::UnknownClient { tx: tx.clone() })?;
+
+                //A client who's account is frozen cannot do any transactions
+                if client.locked {
+                    return Err(ProcessError::LockedAccount { tx });
+                }
+
+                //Check that the disputed transaction is a deposit, or a withdrawal
+                //if the dispute policy allows withdrawals to be disputed
+                let disputable = match disputed_tx.tx_type {
+                    TransactionType::Deposit => true,
+                    TransactionType::Withdrawal => config.disputes != DisputePolicy::DepositsOnly,
+                    _ => false,
+                };
+                if !disputable {
+                    return Err(ProcessError::NotDisputable { tx });
+                }
+
+                //Unwrap the amount, as we've already ensured it exists if the transaction
+                //is a deposit or withdrawal
+                let amount = disputed_tx.amount.unwrap();
+
+                let (available, held, total) = if config.disputes.reverses(&disputed_tx.tx_type) {
+                    //Hold back the withdrawn amount as a potential credit, increasing
+                    //the held and total funds while leaving available funds unchanged
+                    (
+                        Some(client.available),
+                        client.held.checked_add(amount),
+                        client.total.checked_add(amount),
+                    )
+                } else {
+                    //Move the amount of the disputed transaction from available to held funds
+                    (
+                        client.available.checked_sub(amount),
+                        client.held.checked_add(amount),
+                        Some(client.total),
+                    )
+                };
+                let (Some(available), Some(held), Some(total)) = (available, held, total) else {
+                    return Err(ProcessError::Overflow { tx });
+                };
+
+                //Mark the transaction as disputed in the store before updating the
+                //client, so that a storage error leaves the client untouched
+                if let Err(source) = processed_txs.set_state(tx.id, next_state) {
+                    return Err(ProcessError::Storage { tx, source });
+                }
+
+                client.available = available;
+                client.held = held;
+                client.total = total;
+
+                //Store a copy of the disputed transaction in the held_txs hashmap
+                //for easier future reference
+                held_txs.insert(tx.id, stored.tx);
+            }
+            TransactionType::Resolve => {
+                //Lookup the transaction referenced by the resolve. It's only removed from
+                //the held_txs hashmap once the resolve has succeeded
+                let Some(disputed_tx) = held_txs.get(&tx.id) else {
+                    return Err(not_disputed(tx, processed_txs.as_ref()));
+                };
+
+                //Only the client who made the disputed transaction can resolve it
+                if disputed_tx.client != tx.client {
+                    let owner = disputed_tx.client;
+                    return Err(ProcessError::ClientMismatch { tx, owner });
+                }
+
+                //Get the client record from the hashmap. This should always exist
+                //but check error just for safety
+                let client = clients
+                    .get_mut(&disputed_tx.client)
+                    .ok_or_else(|| ProcessError::UnknownClient { tx: tx.clone() })?;
+
+                //A client who's account is frozen cannot do any transactions
+                if client.locked {
+                    return Err(ProcessError::LockedAccount { tx });
+                }
+
+                //Unwrap the amount, as we've already ensured it exists if the transaction
+                //is in the disputed txs hashmap
+                let amount = disputed_tx.amount.unwrap();
+
+                let (available, held, total) = if config.disputes.reverses(&disputed_tx.tx_type) {
+                    //The withdrawal stands, so release the hold on the withdrawn
+                    //amount, decreasing the held and total funds
+                    (
+                        Some(client.available),
+                        client.held.checked_sub(amount),
+                        client.total.checked_sub(amount),
+                    )
+                } else {
+                    //Move the amount of the disputed transaction from held back to available funds
+                    (
+                        client.available.checked_add(amount),
+                        client.held.checked_sub(amount),
+                        Some(client.total),
+                    )
+                };
+                let (Some(available), Some(held), Some(total)) = (available, held, total) else {
+                    return Err(ProcessError::Overflow { tx });
+                };
+
+                //Mark the transaction as resolved in the store before updating the
+                //client, so that a storage error leaves the client untouched
+                if let Err(source) = processed_txs.set_state(tx.id, TxState::Resolved) {
+                    return Err(ProcessError::Storage { tx, source });
+                }
+
+                client.available = available;
+                client.held = held;
+                client.total = total;
+
+                //Remove the disputed transaction from the held_txs hashmap
+                held_txs.remove(&tx.id);
+            }
+            TransactionType::Chargeback => {
+                //Lookup the transaction referenced by the chargeback. It's only removed from
+                //the held_txs hashmap once the chargeback has succeeded
+                let Some(disputed_tx) = held_txs.get(&tx.id) else {
+                    return Err(not_disputed(tx, processed_txs.as_ref()));
+                };
+
+                //Only the client who made the disputed transaction can chargeback it
+                if disputed_tx.client != tx.client {
+                    let owner = disputed_tx.client;
+                    return Err(ProcessError::ClientMismatch { tx, owner });
+                }
+
+                //Get the client record from the hashmap. This should always exist
+                //but check error just for safety
+                let client = clients
+                    .get_mut(&disputed_tx.client)
+                    .ok_or_else(|| ProcessError::UnknownClient { tx: tx.clone() })?;
+
+                //A client who's account is frozen cannot do any transactions
+                if client.locked {
+                    return Err(ProcessError::LockedAccount { tx });
+                }
+
+                //Unwrap the amount, as we've already ensured it exists if the transaction
+                //is in the disputed txs hashmap
+                let amount = disputed_tx.amount.unwrap();
+
+                let (available, held, total) = if config.disputes.reverses(&disputed_tx.tx_type) {
+                    //The withdrawal is reversed, so the held amount is
+                    //credited back to the client's available funds
+                    (
+                        client.available.checked_add(amount),
+                        client.held.checked_sub(amount),
+                        Some(client.total),
+                    )
+                } else {
+                    //Withdraw the amount of the disputed transaction from the held and total funds
+                    (
+                        Some(client.available),
+                        client.held.checked_sub(amount),
+                        client.total.checked_sub(amount),
+                    )
+                };
+                let (Some(available), Some(held), Some(total)) = (available, held, total) else {
+                    return Err(ProcessError::Overflow { tx });
+                };
+
+                //Mark the transaction as charged back in the store before updating the
+                //client, so that a storage error leaves the client untouched
+                if let Err(source) = processed_txs.set_state(tx.id, TxState::ChargedBack) {
+                    return Err(ProcessError::Storage { tx, source });
+                }
+
+                client.available = available;
+                client.held = held;
+                client.total = total;
+
+                //Set the client's account to locked
+                client.locked = true;
+
+                //Remove the disputed transaction from the held_txs hashmap
+                held_txs.remove(&tx.id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the error for a resolve or chargeback which references a transaction
+/// that isn't currently disputed
+///
+/// If the transaction is still retained, its state explains why, e.g. it was
+/// already resolved, otherwise it's reported as an unknown dispute
+fn not_disputed(tx: Transaction, processed_txs: &dyn TransactionStore) -> ProcessError {
+    match processed_txs.get(tx.id) {
+        Ok(Some(stored)) => ProcessError::IllegalTransition {
+            state: stored.state,
+            tx,
+        },
+        Ok(None) => ProcessError::UnknownDispute { tx },
+        Err(source) => ProcessError::Storage { tx, source },
+    }
+}
@@ -0,0 +1,20 @@
+//! A streaming processor for client transactions
+//!
+//! The `Ledger` owns the state of every client and applies deposits, withdrawals,
+//! disputes, resolves, and chargebacks to it one transaction at a time, so it can
+//! be embedded anywhere transactions arrive, not just behind the CSV command line tool.
+
+pub mod checkpoint;
+pub mod datatypes;
+pub mod error;
+pub mod journal;
+mod ledger;
+pub mod store;
+#[cfg(test)]
+mod tests;
+pub mod validate;
+
+pub use checkpoint::Checkpoint;
+pub use journal::{Journal, JournalEntry};
+pub use ledger::{DedupPolicy, DisputePolicy, Ledger, LedgerConfig, OutputOrder};
+pub use store::{DiskStore, Retention, TransactionStore, DEFAULT_CAPACITY};
@@ -1,247 +1,658 @@
-use crate::datatypes::{Client, RingBuffer, Transaction, TransactionType};
-use csv::{ReaderBuilder, Writer};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+mod cli;
+mod events;
+mod input;
+mod partial;
+mod records;
+mod rejects;
+mod statement;
+mod summary;
+
+use clap::Parser;
+use cli::{
+    AsOfArgs, Cli, Command, LedgerArgs, OutputFormat, ProcessArgs, RecoverArgs, StatementArgs,
+    ValidateArgs,
+};
+use csv::Writer;
+use events::BalanceEvent;
+use input::{Input, Row, RowError};
+use partial::PartialFile;
+use records::RecordWriter;
+use rejects::{Rejection, RejectsWriter};
+use statement::{StatementLine, Status};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use summary::Summary;
+use transaction_processor::datatypes::Client;
+use transaction_processor::error::{CheckpointError, ErrorCategory, ProcessError};
+use transaction_processor::validate::validate;
+use transaction_processor::{
+    Checkpoint, DiskStore, Journal, Ledger, LedgerConfig, OutputOrder, Retention,
+};
+
+///Exit codes, apart from 0 for success and 2 for invalid arguments, which clap uses.
+///In strict mode, a rejected row exits with a code for the category of its error
+const EXIT_FAILURE: u8 = 1;
+const EXIT_PARSE_ERROR: u8 = 3;
+const EXIT_VALIDATION_ERROR: u8 = 4;
+const EXIT_RULE_ERROR: u8 = 5;
+const EXIT_STORAGE_ERROR: u8 = 6;
+
+/// An error which stops the run, along with the code the process exits with
+struct Fatal {
+    message: String,
+    code: u8,
+}
 
-mod datatypes;
-#[cfg(test)]
-mod tests;
+impl From<String> for Fatal {
+    fn from(message: String) -> Self {
+        Fatal {
+            message,
+            code: EXIT_FAILURE,
+        }
+    }
+}
 
 ///Processes a CSV of transactions and outputs the final state of all clients
-fn main() {
-    //Parse args
-    let args: Vec<String> = std::env::args().collect();
-
-    //Validate number of args
-    if args.len() > 2 {
-        eprintln!("Usage: {} <input.csv>", args[0]);
-        std::process::exit(1);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    //Running without a subcommand is the same as running `process`
+    let result = match cli.command {
+        Some(Command::Process(args)) => process(args),
+        Some(Command::Validate(args)) => validate_input(args),
+        Some(Command::Recover(args)) => recover(args),
+        Some(Command::AsOf(args)) => balances_as_of(args),
+        Some(Command::Statement(args)) => statement(args),
+        None => process(cli.process),
+    };
+
+    //Errors which stop the run are reported here, rather than panicking
+    match result {
+        Ok(code) => code,
+        Err(fatal) => {
+            eprintln!("error: {}", fatal.message);
+            ExitCode::from(fatal.code)
+        }
     }
+}
 
-    //Open the input file, if it doesn't exist, panic
-    let input_file = File::open(&args[1]).expect("file to exist");
-
-    //Use a buffered reader to read the input file to avoid
-    //making a system call for each iteration of the main loop
-    let input_buf = BufReader::new(input_file);
-
-    //configure csv reader
-    let mut csv_reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .delimiter(b',')
-        .from_reader(input_buf);
+///Process every transaction in the input and write out the final state of all clients
+fn process(args: ProcessArgs) -> Result<ExitCode, Fatal> {
+    let inputs = open_inputs(&args.inputs)?;
+    let mut error_log = open_error_log(args.error_log.as_deref())?;
+    let mut rejects = args
+        .rejects
+        .as_deref()
+        .map(|path| RejectsWriter::create(path, args.rejects_format, "rejects file"))
+        .transpose()?;
+    //Events are written as the run goes, so they're only put in place if it finishes
+    let mut events = args
+        .events
+        .as_deref()
+        .map(|path| {
+            PartialFile::create(path)
+                .map(|file| RecordWriter::new(file, args.events_format, "events file"))
+                .map_err(|e| format!("could not create events file {}: {e}", path.display()))
+        })
+        .transpose()?;
+
+    //Refuse to overwrite the journal of a run which didn't finish, since it's
+    //the only record of what that run applied
+    let mut journal = match &args.journal {
+        Some(path) => {
+            let unfinished = Journal::read(path)
+                .map_err(|e| format!("could not read journal {}: {e}", path.display()))?;
+            if !unfinished.is_empty() {
+                return Err(format!(
+                    "journal {} has transactions from a run which didn't finish, \
+                     run `recover` on it first",
+                    path.display()
+                )
+                .into());
+            }
+            let journal = Journal::open(path, args.journal_sync_interval)
+                .map_err(|e| format!("could not open journal {}: {e}", path.display()))?;
+            Some(journal)
+        }
+        None => None,
+    };
 
-    //Create relevant mutable state to store and update client records, processed transactions,
-    //and held transactions
-    let mut clients: HashMap<u16, Client> = HashMap::new();
-    let mut processed_txs: RingBuffer<Transaction> = RingBuffer::with_capacity(10000);
-    let mut held_txs: HashMap<u32, Transaction> = HashMap::new();
+    let mut ledger = open_ledger(&args.ledger, LedgerUse::Process)?;
 
     //Process each transaction in the input and update the state of the clients
+    let mut summary = Summary::start();
 
+    //The inputs are processed one after the other into the same ledger, as if
+    //they were a single input
     //For each transaction record, if it deserializes correctly, process the transaction.
-    //Or if errors are returned, ignore the transaction and continue to the next one
-    for csv_result in csv_reader.deserialize::<Transaction>() {
-        //map_err is used to convert the csv::Error to a String
-        //to avoid unnecessary error handling complexity
-        let process_result = csv_result.map_err(|e| e.to_string()).and_then(|tx_record| {
-            process_transaction(tx_record, &mut clients, &mut processed_txs, &mut held_txs)
-        });
-
-        if let Err(e) = process_result {
-            eprintln!("{e}");
+    //Or if errors are returned, log the transaction and continue to the next one,
+    //unless running in strict mode
+    for mut input in inputs {
+        while let Some((row, parse_result)) = input.next_row() {
+            //Parse errors are already ProcessErrors, so parse and processing
+            //errors can be handled together
+            let process_result = parse_result.and_then(|tx_record| {
+                //Only copy the transaction if it needs to be journaled once it's applied
+                let journal_tx = journal.is_some().then(|| tx_record.clone());
+                //Only look up the balances before the transaction if there's an events file
+                let before = events
+                    .is_some()
+                    .then(|| ledger.client(tx_record.client).cloned());
+                let (tx_type, client, tx_id) =
+                    (tx_record.tx_type.clone(), tx_record.client, tx_record.id);
+                ledger
+                    .apply(tx_record)
+                    .map(|()| (tx_type, client, tx_id, journal_tx, before))
+            });
+
+            let e = match process_result {
+                Ok((tx_type, client, tx_id, journal_tx, before)) => {
+                    if let (Some(journal), Some(tx)) = (&mut journal, journal_tx) {
+                        journal
+                            .append(&input.name, row.line, row.byte, &tx)
+                            .map_err(|e| format!("could not write to journal: {e}"))?;
+                    }
+                    //An applied transaction always leaves the client with a record
+                    if let (Some(events), Some(before), Some(after)) =
+                        (&mut events, before, ledger.client(client))
+                    {
+                        events.write(&BalanceEvent::new(tx_id, tx_type.clone(), before, after))?;
+                    }
+                    summary.applied(&tx_type);
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            summary.rejected(&e);
+            reject(&mut rejects, &input, &row, &e)?;
+            //In strict mode, stop before any output has been written
+            if args.strict {
+                flush_rejects(&mut rejects)?;
+                //Nothing the run applied was kept, so there's nothing to recover and
+                //it can be run again from the same start once the row is fixed
+                clear_journal(args.journal.as_deref(), journal.take())?;
+                let error = RowError {
+                    input: &input,
+                    row: &row,
+                    error: &e,
+                };
+                return Err(Fatal {
+                    message: format!("stopped at the first rejected row: {error}"),
+                    code: strict_exit_code(&e),
+                });
+            }
+            log_error(&mut error_log, &input, &row, &e)?;
         }
     }
+    flush_rejects(&mut rejects)?;
+
+    //Make sure the transaction history is fully written out
+    ledger
+        .flush()
+        .map_err(|e| format!("could not write transaction history: {e}"))?;
+
+    summary.finish(&ledger);
+    if let Some(path) = &args.summary_file {
+        fs::write(path, format!("{summary}\n"))
+            .map_err(|e| format!("could not write summary to {}: {e}", path.display()))?;
+    } else if args.summary {
+        eprintln!("{summary}");
+    }
 
-    //Round the clients' fund values to 4 decimal places.
-    //Truncating caused unexpected results in the tests.
-    for client in clients.values_mut() {
-        client.available = (client.available * 10000.0f64).round() / 10000.0f64;
-        client.total = (client.total * 10000.0f64).round() / 10000.0f64;
-        client.held = (client.held * 10000.0f64).round() / 10000.0f64;
+    //Let the user know if the retention was too small for some disputes
+    let evicted_disputes = ledger.evicted_dispute_failures();
+    if evicted_disputes > 0 {
+        eprintln!(
+            "{evicted_disputes} dispute(s) referenced transactions which were no longer \
+             retained, consider increasing --retention (currently {})",
+            args.ledger.retention
+        );
     }
 
-    //Create the csv writer
-    let mut csv_writer = Writer::from_writer(std::io::stdout());
+    //Write the client records, sorted so the output is the same from run to run
+    let clients = ledger.clients_ordered(args.order);
+    match &args.output {
+        Some(path) => write_atomically(path, |output| write_clients(output, args.format, &clients))
+            .map_err(|e| format!("could not write client records to {}: {e}", path.display()))?,
+        None => write_clients(&mut io::stdout().lock(), args.format, &clients)
+            .map_err(|e| format!("could not write client records: {e}"))?,
+    }
 
-    //Serialize the client records to stdout.
-    //Since row order is irrelevant, iterating over
-    //the hashmap values is sufficient. (undefined order)
-    for client in clients.values() {
-        csv_writer
-            .serialize(client)
-            //Expect is used here as the serialization should not fail
-            .expect("CSV serialization to succeed");
+    if let (Some(path), Some(events)) = (&args.events, events) {
+        events
+            .into_inner()?
+            .commit()
+            .map_err(|e| format!("could not write events file {}: {e}", path.display()))?;
     }
+
+    //Save the full state of the ledger for the next run to resume from. This is
+    //the last thing which can fail before the journal is emptied, since a checkpoint
+    //which already has the journaled transactions can't have them replayed onto it
+    if let Some(path) = &args.checkpoint {
+        write_atomically(path, |file| Ok(ledger.checkpoint().save(file)?))
+            .map_err(|e| format!("could not save checkpoint to {}: {e}", path.display()))?;
+    }
+
+    //The run finished, so the journal isn't needed to recover it
+    clear_journal(args.journal.as_deref(), journal.take())?;
+
+    Ok(ExitCode::SUCCESS)
 }
 
-/// Processes a transaction record and updates the client, processed transactions,
-/// and held transactions state accordingly
-///
-/// Errors are returned as strings to be printed to stderr
-fn process_transaction(
-    tx: Transaction,
-    clients: &mut HashMap<u16, Client>,
-    processed_txs: &mut RingBuffer<Transaction>,
-    held_txs: &mut HashMap<u32, Transaction>,
-) -> Result<(), String> {
-    match tx.tx_type {
-        TransactionType::Deposit => {
-            //Get the client record from the hashmap, or create a new one
-            let client = clients
-                .entry(tx.client)
-                .or_insert_with(|| Client::new(tx.client));
-
-            //A client who's account is frozen cannot do any transactions
-            if client.locked {
-                return Err(format!("Client is locked: {tx:?}"));
-            }
+///Close and empty the journal of a run which ended without needing to be recovered
+fn clear_journal(path: Option<&Path>, journal: Option<Journal>) -> Result<(), String> {
+    if let (Some(path), Some(journal)) = (path, journal) {
+        drop(journal);
+        Journal::clear(path)
+            .map_err(|e| format!("could not clear journal {}: {e}", path.display()))?;
+    }
+    Ok(())
+}
 
-            //Unwrap the amount or return an error if it doesn't exist
-            let amount = tx
-                .amount
-                .ok_or_else(|| format!("Deposit transaction missing amount: {tx:?}"))?;
+/// What a command uses its ledger for, which decides whether a history directory may be
+/// written to and whether the run has to be repeatable
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LedgerUse {
+    ///Processing new input
+    Process,
+    ///Replaying a journal, which has to reach exactly the state the run it came from did
+    Recover,
+    ///Replaying input to report on it, which never writes to the history
+    Query,
+}
 
-            //increment the client's available and total funds
-            client.available += amount;
-            client.total += amount;
+///Create the ledger which stores and updates client records, processed transactions,
+///and held transactions, starting from a checkpoint or snapshot if given
+fn open_ledger(args: &LedgerArgs, ledger_use: LedgerUse) -> Result<Ledger, String> {
+    //Age retention evicts by the wall clock, so replaying the same rows could
+    //evict different transactions than the run being replayed did
+    if ledger_use != LedgerUse::Process {
+        if let Retention::Age(_) = args.retention {
+            return Err(format!(
+                "--retention {} evicts by the time transactions were processed, so \
+                 replaying them isn't repeatable, use a count or `unbounded` instead",
+                args.retention
+            ));
+        }
+    }
 
-            //push the processed transaction into the buffer for future
-            //reference if needed
-            processed_txs.push(tx);
+    //If a history directory was given, keep every processed transaction
+    //there instead of keeping the most recent ones in memory
+    let config = LedgerConfig {
+        retention: args.retention,
+        max_amount: args.max_amount,
+        ..LedgerConfig::default()
+    };
+    //When resuming, the history from the run being resumed is kept
+    let mut ledger = match &args.history_dir {
+        Some(dir) => {
+            let read_only = ledger_use == LedgerUse::Query;
+            let store = match (args.resume.is_some(), read_only) {
+                (true, false) => DiskStore::open(dir),
+                (false, false) => DiskStore::create(dir),
+                (true, true) => DiskStore::open_read_only(dir),
+                //The history already in the directory only belongs to the state
+                //of the checkpoint it was taken with
+                (false, true) => {
+                    return Err("--history-dir is only read from by this command, \
+                                so it needs --resume with a checkpoint taken with it"
+                        .to_string())
+                }
+            };
+            let store = store.map_err(|e| {
+                format!(
+                    "could not open transaction history in {}: {e}",
+                    dir.display()
+                )
+            })?;
+            Ledger::with_store(config, store)
         }
-        TransactionType::Withdrawal => {
-            //Get the client record from the hashmap, or create a new one
-            let client = clients
-                .entry(tx.client)
-                .or_insert_with(|| Client::new(tx.client));
-
-            //A client who's account is frozen cannot do any transactions
-            if client.locked {
-                return Err(format!("Client is locked: {tx:?}"));
-            }
+        None => Ledger::with_config(config),
+    };
+
+    //Carry on from the full state at the end of an earlier run, if given
+    if let Some(path) = &args.resume {
+        let restore_result = File::open(path)
+            .map_err(CheckpointError::from)
+            .and_then(|file| Checkpoint::load(BufReader::new(file)))
+            .and_then(|checkpoint| ledger.restore(checkpoint));
+        restore_result.map_err(|e| format!("could not resume from {}: {e}", path.display()))?;
+    }
 
-            //Unwrap the amount or return an error if it doesn't exist
-            let amount = tx
-                .amount
-                .ok_or_else(|| format!("Withdrawal transaction missing amount: {tx:?}"))?;
+    //Start from the balances at the end of an earlier run, if given
+    if let Some(path) = &args.snapshot {
+        let load_result = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                ledger
+                    .load_snapshot(BufReader::new(file))
+                    .map_err(|e| e.to_string())
+            });
+        load_result.map_err(|e| format!("could not load snapshot {}: {e}", path.display()))?;
+    }
 
-            //Check if the client has enough funds to withdraw.
-            //This will also catch a new client trying to withdraw
-            //before depositing, but perhaps that should be a separate error ?
-            if client.available < amount {
-                return Err(format!("Insufficient funds for withdrawal: {tx:?}"));
-            }
+    Ok(ledger)
+}
 
-            //Decrement the client's available and total funds
-            client.available -= amount;
-            client.total -= amount;
+///Rebuild the state of a run which didn't finish by replaying its journal on top of
+///the checkpoint it started from, then save it and report where to carry on from
+fn recover(args: RecoverArgs) -> Result<ExitCode, Fatal> {
+    let mut ledger = open_ledger(&args.ledger, LedgerUse::Recover)?;
+    let entries = Journal::read(&args.journal)
+        .map_err(|e| format!("could not read journal {}: {e}", args.journal.display()))?;
+
+    //Every journaled transaction was accepted the first time round, so replaying them
+    //from the same starting state should accept them all again
+    let replayed = entries.len();
+    let mut last_position = None;
+    for entry in entries {
+        let tx_id = entry.tx.id;
+        ledger.apply(entry.tx).map_err(|e| {
+            format!(
+                "could not replay transaction {tx_id} from {}:{}, check the options \
+                 match the run being recovered: {e}",
+                entry.input, entry.line
+            )
+        })?;
+        last_position = Some((entry.input, entry.line, entry.byte));
+    }
+    ledger
+        .flush()
+        .map_err(|e| format!("could not write transaction history: {e}"))?;
+
+    //Save the recovered state before emptying the journal, so that it's always
+    //in one or the other
+    write_atomically(&args.checkpoint, |file| {
+        Ok(ledger.checkpoint().save(file)?)
+    })
+    .map_err(|e| {
+        format!(
+            "could not save checkpoint to {}: {e}",
+            args.checkpoint.display()
+        )
+    })?;
+    Journal::clear(&args.journal)
+        .map_err(|e| format!("could not clear journal {}: {e}", args.journal.display()))?;
+
+    println!(
+        "Recovered {replayed} transaction(s) into {}",
+        args.checkpoint.display()
+    );
+    match last_position {
+        Some((input, line, byte)) => println!(
+            "The last applied row was {input}:{line} (byte {byte}), \
+             resume with the rows after it"
+        ),
+        None => println!("No rows were applied, resume from the start of the input"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-            //Push the processed transaction into the buffer for future
-            //reference if needed
-            processed_txs.push(tx);
-        }
-        TransactionType::Dispute => {
-            //Lookup the transaction referenced by the dispute
-            let disputed_tx = processed_txs
-                .get_by_tx(tx.id)
-                .ok_or_else(|| format!("Dispute references non-existent transaction: {tx:?}"))?;
-
-            //Get the client record from the hashmap. This should always exist
-            //but check error just for safety
-            let client = clients
-                .get_mut(&disputed_tx.client)
-                .ok_or_else(|| format!("Dispute references non-existent client: {tx:?}"))?;
-
-            //A client who's account is frozen cannot do any transactions
-            if client.locked {
-                return Err(format!("Client is locked: {tx:?}"));
-            }
+///Write a file by writing a temporary file next to it first, which is synced
+///to disk and then renamed over it, so a failed run never leaves a partial file
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = PartialFile::create(path)?;
+    write(&mut file)?;
+    file.commit()?;
+    Ok(())
+}
 
-            //Check that the disputed transaction is a deposit or withdrawal
-            if disputed_tx.tx_type != TransactionType::Deposit
-                && disputed_tx.tx_type != TransactionType::Withdrawal
-            {
-                return Err(format!(
-                    "Dispute references non-deposit/withdrawal transaction: {tx:?}"
-                ));
+///The exit code for a row rejected in strict mode, by the category of its error
+fn strict_exit_code(e: &ProcessError) -> u8 {
+    match e.category() {
+        ErrorCategory::Parse => EXIT_PARSE_ERROR,
+        ErrorCategory::Validation => EXIT_VALIDATION_ERROR,
+        ErrorCategory::Rule => EXIT_RULE_ERROR,
+        ErrorCategory::Storage => EXIT_STORAGE_ERROR,
+    }
+}
+
+///Replay the input up to the given transaction or row, and write out the state of
+///the selected clients right before it
+fn balances_as_of(args: AsOfArgs) -> Result<ExitCode, Fatal> {
+    let inputs = open_inputs(&args.inputs)?;
+    //Only replaying the input, so none of it may be written to the history
+    let mut ledger = open_ledger(&args.ledger, LedgerUse::Query)?;
+
+    let mut rows = 0u64;
+    let mut stopped_at = None;
+    'inputs: for mut input in inputs {
+        while let Some((row, parse_result)) = input.next_row() {
+            rows += 1;
+            //A row which didn't parse can still be the one being looked for
+            let (_, tx_id) = input.ids(&row, parse_result.as_ref().ok());
+            let reached = match args.before_tx {
+                Some(id) => tx_id == Some(id),
+                None => args.before_row == Some(rows),
+            };
+            if reached {
+                stopped_at = Some(format!("{}:{} (byte {})", input.name, row.line, row.byte));
+                break 'inputs;
             }
 
-            //Unwrap the amount, as we've already ensured it exists if the transaction
-            //is a deposit or withdrawal
-            let amount = disputed_tx.amount.unwrap();
+            //Rejected rows are skipped, as they are when processing
+            let _ = parse_result.and_then(|tx_record| ledger.apply(tx_record));
+        }
+    }
 
-            //Decrease the available funds by the amount of the disputed transaction
-            client.available -= amount;
-            //Increase the held funds by the amount of the disputed transaction
-            client.held += amount;
+    //Replaying the whole input would give the final state, not the state asked for
+    let Some(stopped_at) = stopped_at else {
+        let message = match args.before_tx {
+            Some(id) => format!("transaction {id} was not found in the input"),
+            None => format!("the input only has {rows} row(s)"),
+        };
+        return Err(message.into());
+    };
+    eprintln!("Replayed {} row(s), stopped before {stopped_at}", rows - 1);
+
+    let clients = if args.clients.is_empty() {
+        ledger.clients_ordered(OutputOrder::ClientId)
+    } else {
+        args.clients
+            .iter()
+            .map(|&id| {
+                ledger
+                    .client(id)
+                    .ok_or_else(|| format!("client {id} has no record before {stopped_at}"))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    write_clients(&mut io::stdout().lock(), args.format, &clients)
+        .map_err(|e| format!("could not write client records: {e}"))?;
+    Ok(ExitCode::SUCCESS)
+}
 
-            //Store a copy of the disputed transaction in the held_txs hashmap
-            //for easier future reference
-            held_txs.insert(tx.id, disputed_tx.clone());
+///Process the input and write a statement of every row belonging to the selected
+///clients, with their balances after each one
+fn statement(args: StatementArgs) -> Result<ExitCode, Fatal> {
+    let inputs = open_inputs(&args.inputs)?;
+    //Only reporting on the input, so none of it may be written to the history
+    let mut ledger = open_ledger(&args.ledger, LedgerUse::Query)?;
+
+    let write_statement = |output: &mut dyn Write| -> Result<(), Box<dyn Error>> {
+        let mut statement = RecordWriter::new(output, args.format, "statement");
+        for mut input in inputs {
+            while let Some((row, parse_result)) = input.next_row() {
+                let (client, tx) = input.ids(&row, parse_result.as_ref().ok());
+                let (tx_type, amount) = match &parse_result {
+                    Ok(tx_record) => (Some(tx_record.tx_type.clone()), tx_record.amount),
+                    Err(_) => (None, None),
+                };
+
+                //Every row is applied, since other clients' rows still count
+                //towards duplicate checks
+                let result = parse_result.and_then(|tx_record| ledger.apply(tx_record));
+                let Some(client) = client.filter(|client| args.clients.contains(client)) else {
+                    continue;
+                };
+
+                //A client's first rows can be rejected before it has a record
+                let record = ledger
+                    .client(client)
+                    .cloned()
+                    .unwrap_or_else(|| Client::new(client));
+                let (status, reason) = match &result {
+                    Ok(()) => (Status::Applied, None),
+                    Err(e) => (Status::Rejected, Some(e.code())),
+                };
+                statement.write(&StatementLine {
+                    input: &input.name,
+                    line: row.line,
+                    client,
+                    tx,
+                    tx_type,
+                    amount,
+                    status,
+                    reason,
+                    available: record.available,
+                    held: record.held,
+                    total: record.total,
+                    locked: record.locked,
+                })?;
+            }
         }
-        TransactionType::Resolve => {
-            //Lookup the transaction referenced by the resolve
-            let disputed_tx = held_txs
-                .remove(&tx.id)
-                .ok_or_else(|| format!("Resolve references non-existent dispute: {tx:?}"))?;
-
-            //Get the client record from the hashmap. This should always exist
-            //but check error just for safety
-            let client = clients
-                .get_mut(&disputed_tx.client)
-                .ok_or_else(|| format!("Resolve references non-existent client: {tx:?}"))?;
-
-            //A client who's account is frozen cannot do any transactions
-            if client.locked {
-                return Err(format!("Client is locked: {tx:?}"));
+        statement.flush()?;
+        Ok(())
+    };
+
+    match &args.output {
+        Some(path) => write_atomically(path, write_statement)
+            .map_err(|e| format!("could not write statement to {}: {e}", path.display()))?,
+        None => write_statement(&mut io::stdout().lock())
+            .map_err(|e| format!("could not write statement: {e}"))?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+///Write client records in the given format
+fn write_clients(
+    output: &mut dyn Write,
+    format: OutputFormat,
+    clients: &[&Client],
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Csv => {
+            let mut csv_writer = Writer::from_writer(output);
+            for client in clients {
+                csv_writer.serialize(client)?;
             }
+            csv_writer.flush()?;
+        }
+        //A single array of client objects
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *output, clients)?;
+            writeln!(output)?;
+            output.flush()?;
+        }
+        //One client object per line
+        OutputFormat::Jsonl => {
+            for client in clients {
+                serde_json::to_writer(&mut *output, client)?;
+                writeln!(output)?;
+            }
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
 
-            //Unwrap the amount, as we've already ensured it exists if the transaction
-            //is in the disputed txs hashmap
-            let amount = disputed_tx.amount.unwrap();
+///Check that every row of the input deserializes and is well formed, without applying
+///any of them. Fails if any row is rejected
+fn validate_input(args: ValidateArgs) -> Result<ExitCode, Fatal> {
+    let inputs = open_inputs(&args.inputs)?;
+    let mut error_log = open_error_log(args.error_log.as_deref())?;
+    let mut rejects = args
+        .rejects
+        .as_deref()
+        .map(|path| RejectsWriter::create(path, args.rejects_format, "rejects file"))
+        .transpose()?;
+
+    let mut rows = 0u64;
+    let mut rejected = 0u64;
+    for mut input in inputs {
+        while let Some((row, parse_result)) = input.next_row() {
+            rows += 1;
+            let validate_result =
+                parse_result.and_then(|tx_record| validate(tx_record, args.max_amount));
+
+            if let Err(e) = validate_result {
+                rejected += 1;
+                reject(&mut rejects, &input, &row, &e)?;
+                log_error(&mut error_log, &input, &row, &e)?;
+            }
+        }
+    }
+    flush_rejects(&mut rejects)?;
+
+    eprintln!("{rows} row(s) checked, {rejected} rejected");
+    Ok(if rejected == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
 
-            //Decrease the held funds by the amount of the disputed transaction
-            client.held -= amount;
-            //Increase the available funds by the amount of the disputed transaction
-            client.available += amount;
+///Open every input up front, so a missing file is reported before anything is processed
+fn open_inputs(paths: &[PathBuf]) -> Result<Vec<Input>, String> {
+    //Stdin can only be read once, and each input holds it locked while it's open
+    let stdin_count = paths.iter().filter(|path| *path == Path::new("-")).count();
+    if stdin_count > 1 {
+        return Err("`-` can only be given once, since stdin can only be read once".to_string());
+    }
+    paths.iter().map(|path| Input::open(path)).collect()
+}
 
-            //Remove the disputed transaction from the held_txs hashmap
-            held_txs.remove(&disputed_tx.id);
+///Open the file rejected rows are logged to, or stderr if none was given
+fn open_error_log(path: Option<&Path>) -> Result<Box<dyn Write>, String> {
+    match path {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("could not create error log {}: {e}", path.display()))?;
+            Ok(Box::new(BufWriter::new(file)))
         }
-        TransactionType::Chargeback => {
-            //Lookup the transaction referenced by the chargeback
-            let disputed_tx = held_txs
-                .remove(&tx.id)
-                .ok_or_else(|| format!("Chargeback references non-existent dispute: {tx:?}"))?;
-
-            //Get the client record from the hashmap. This should always exist
-            //but check error just for safety
-            let client = clients
-                .get_mut(&disputed_tx.client)
-                .ok_or_else(|| format!("Chargeback references non-existent client: {tx:?}"))?;
-
-            //A client who's account is frozen cannot do any transactions
-            if client.locked {
-                return Err(format!("Client is locked: {tx:?}"));
-            }
-
-            //Unwrap the amount, as we've already ensured it exists if the transaction
-            //is in the disputed txs hashmap
-            let amount = disputed_tx.amount.unwrap();
+        None => Ok(Box::new(io::stderr())),
+    }
+}
 
-            //Decrease the held funds by the amount of the disputed transaction
-            client.held -= amount;
-            //Decrease the total funds by the amount of the disputed transaction
-            client.total -= amount;
+///Log a rejected row, along with where in the input it came from
+fn log_error(
+    error_log: &mut dyn Write,
+    input: &Input,
+    row: &Row,
+    e: &ProcessError,
+) -> Result<(), String> {
+    let error = RowError {
+        input,
+        row,
+        error: e,
+    };
+    writeln!(error_log, "{error}").map_err(|e| format!("could not write to error log: {e}"))
+}
 
-            //Set the client's account to locked
-            client.locked = true;
+///Write a rejected row to the rejects file, if there is one
+fn reject(
+    rejects: &mut Option<RejectsWriter>,
+    input: &Input,
+    row: &Row,
+    e: &ProcessError,
+) -> Result<(), String> {
+    match rejects {
+        Some(rejects) => rejects.write(&Rejection::new(input, row, e)),
+        None => Ok(()),
+    }
+}
 
-            //Remove the disputed transaction from the held_txs hashmap
-            held_txs.remove(&disputed_tx.id);
-        }
+///Write out any buffered rejections, if there is a rejects file
+fn flush_rejects(rejects: &mut Option<RejectsWriter>) -> Result<(), String> {
+    match rejects {
+        Some(rejects) => rejects.flush(),
+        None => Ok(()),
     }
-    Ok(())
 }
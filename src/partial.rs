@@ -0,0 +1,58 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A file which is written next to its destination under a `.partial` name, and
+/// only renamed into place once it's complete, so a failed run never leaves a
+/// partial file behind
+///
+/// If it's dropped without being committed, the partial file is removed.
+pub struct PartialFile {
+    file: BufWriter<File>,
+    path: PathBuf,
+    partial_path: PathBuf,
+    committed: bool,
+}
+
+impl PartialFile {
+    ///Create the partial file for `path`, replacing any left over from an earlier run
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut partial_path = path.as_os_str().to_owned();
+        partial_path.push(".partial");
+        let partial_path = PathBuf::from(partial_path);
+
+        Ok(Self {
+            file: BufWriter::new(File::create(&partial_path)?),
+            path: path.to_path_buf(),
+            partial_path,
+            committed: false,
+        })
+    }
+
+    ///Sync the file to disk and rename it over its destination
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        fs::rename(&self.partial_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for PartialFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.partial_path);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::cli::RecordFormat;
+use csv::Writer;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Writes records one at a time, either as CSV or as one JSON object per line
+pub struct RecordWriter<W: Write> {
+    ///What the records are, for error messages
+    name: &'static str,
+    output: Output<W>,
+}
+
+enum Output<W: Write> {
+    Csv(Box<Writer<W>>),
+    Jsonl(W),
+}
+
+impl RecordWriter<BufWriter<File>> {
+    ///Create a file to write records to, replacing it if it exists
+    pub fn create(path: &Path, format: RecordFormat, name: &'static str) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("could not create {name} {}: {e}", path.display()))?;
+        Ok(Self::new(BufWriter::new(file), format, name))
+    }
+}
+
+impl<W: Write> RecordWriter<W> {
+    ///Write records to `writer`, where `name` describes them in error messages
+    pub fn new(writer: W, format: RecordFormat, name: &'static str) -> Self {
+        let output = match format {
+            RecordFormat::Csv => Output::Csv(Box::new(Writer::from_writer(writer))),
+            RecordFormat::Jsonl => Output::Jsonl(writer),
+        };
+        Self { name, output }
+    }
+
+    ///Write a record
+    pub fn write(&mut self, record: &impl Serialize) -> Result<(), String> {
+        let result: Result<(), Box<dyn Error>> = match &mut self.output {
+            Output::Csv(writer) => writer.serialize(record).map_err(Into::into),
+            Output::Jsonl(writer) => serde_json::to_writer(&mut *writer, record)
+                .map_err(Into::into)
+                .and_then(|_| writeln!(writer).map_err(Into::into)),
+        };
+        result.map_err(|e| format!("could not write to {}: {e}", self.name))
+    }
+
+    ///Write out any buffered records
+    pub fn flush(&mut self) -> Result<(), String> {
+        let result = match &mut self.output {
+            Output::Csv(writer) => writer.flush(),
+            Output::Jsonl(writer) => writer.flush(),
+        };
+        result.map_err(|e| format!("could not write to {}: {e}", self.name))
+    }
+
+    ///Write out any buffered records and return the writer they were written to
+    pub fn into_inner(self) -> Result<W, String> {
+        match self.output {
+            Output::Csv(writer) => writer
+                .into_inner()
+                .map_err(|e| format!("could not write to {}: {}", self.name, e.error())),
+            Output::Jsonl(writer) => Ok(writer),
+        }
+    }
+}
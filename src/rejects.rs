@@ -0,0 +1,61 @@
+use crate::input::{ErrorReason, Input, Row};
+use crate::records::RecordWriter;
+use csv::WriterBuilder;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use transaction_processor::error::ProcessError;
+
+/// A rejected row, as written to the rejects file
+#[derive(Serialize)]
+pub struct Rejection<'a> {
+    ///The name of the input the row was read from
+    pub input: &'a str,
+    pub line: u64,
+    pub byte: u64,
+    ///The client and transaction ID of the row, if they could be read
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    ///The stable code of the error the row was rejected with
+    pub reason: &'static str,
+    pub message: String,
+    ///The original row, as it was read
+    pub row: String,
+}
+
+impl<'a> Rejection<'a> {
+    ///Describe a row from `input` which was rejected with `error`
+    pub fn new(input: &'a Input, row: &Row, error: &ProcessError) -> Self {
+        let (client, tx) = input.ids(row, error.transaction());
+
+        Self {
+            input: &input.name,
+            line: row.line,
+            byte: row.byte,
+            client,
+            tx,
+            reason: error.code(),
+            message: ErrorReason(error).to_string(),
+            row: original_row(row),
+        }
+    }
+}
+
+/// Writes rejected rows to a file, so they can be reviewed and reprocessed
+pub type RejectsWriter = RecordWriter<BufWriter<File>>;
+
+///Reassemble a row as it appeared in the input, quoting fields where needed
+fn original_row(row: &Row) -> String {
+    if row.record.is_empty() {
+        return String::new();
+    }
+
+    let mut writer = WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    //Writing to memory can't fail, and fields are already valid UTF-8
+    let _ = writer.write_record(&row.record);
+    let bytes = writer.into_inner().unwrap_or_default();
+    let row = String::from_utf8_lossy(&bytes);
+    row.strip_suffix('\n').unwrap_or(&row).to_string()
+}
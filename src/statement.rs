@@ -0,0 +1,33 @@
+use serde::Serialize;
+use transaction_processor::datatypes::{Amount, TransactionType};
+
+/// A row of a client's statement: a transaction of the client's, whether it was
+/// applied, and the client's balances once it had been
+#[derive(Serialize)]
+pub struct StatementLine<'a> {
+    ///The name of the input the row was read from
+    pub input: &'a str,
+    pub line: u64,
+    pub client: u16,
+    pub tx: Option<u32>,
+    ///The type and amount of the transaction, if the row could be parsed
+    #[serde(rename = "type")]
+    pub tx_type: Option<TransactionType>,
+    pub amount: Option<Amount>,
+    pub status: Status,
+    ///The stable code of the error the row was rejected with, if it was
+    pub reason: Option<&'static str>,
+    ///The client's balances after the row
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// Whether a row was applied to the client's balances
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Applied,
+    Rejected,
+}
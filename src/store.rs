@@ -0,0 +1,294 @@
+use crate::datatypes::{StoredTransaction, TxState};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+mod disk;
+
+pub use disk::DiskStore;
+
+/// The default number of processed transactions kept for dispute lookups
+pub const DEFAULT_CAPACITY: usize = 10000;
+
+/// Determines how long processed transactions are kept for dispute lookups
+///
+/// A dispute referencing a transaction which is no longer retained fails, so the
+/// retention trades memory for how late a dispute may arrive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    ///Keep the most recent `n` processed transactions
+    Count(usize),
+    ///Keep processed transactions for a length of time after they were processed
+    Age(Duration),
+    ///Never evict processed transactions
+    Unbounded,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention::Count(DEFAULT_CAPACITY)
+    }
+}
+
+impl FromStr for Retention {
+    type Err = String;
+
+    ///Parses a retention of `unbounded`, a number of transactions such as `50000`,
+    ///or an age with a unit of seconds, minutes, or hours, such as `90s`, `30m`, or `2h`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!("invalid retention {s:?}, expected `unbounded`, a count, or an age like `30m`")
+        };
+
+        if s.eq_ignore_ascii_case("unbounded") {
+            return Ok(Retention::Unbounded);
+        }
+        if let Ok(count) = s.parse() {
+            return Ok(Retention::Count(count));
+        }
+
+        let (value, unit) = s.split_at(s.len().saturating_sub(1));
+        let seconds_per_unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            _ => return Err(err()),
+        };
+        let value: u64 = value.parse().map_err(|_| err())?;
+        let seconds = value.checked_mul(seconds_per_unit).ok_or_else(err)?;
+        Ok(Retention::Age(Duration::from_secs(seconds)))
+    }
+}
+
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Retention::Count(count) => write!(f, "{count}"),
+            Retention::Age(age) => write!(f, "{}s", age.as_secs()),
+            Retention::Unbounded => f.write_str("unbounded"),
+        }
+    }
+}
+
+/// Stores processed deposits and withdrawals so that disputes can look them up by ID
+///
+/// Transactions are returned by value rather than by reference, so that a store
+/// doesn't need to keep them in memory. Stores which do touch the disk report
+/// I/O errors, which the ledger surfaces as `ProcessError::Storage`
+pub trait TransactionStore: Send {
+    ///Look up a transaction by its ID
+    fn get(&self, id: u32) -> io::Result<Option<StoredTransaction>>;
+
+    ///Store a newly processed transaction, returning any transaction
+    ///which was evicted from the store to make room for it
+    fn push(&mut self, stored: StoredTransaction) -> io::Result<Option<StoredTransaction>>;
+
+    ///Update the state of a stored transaction. Does nothing if it isn't stored
+    fn set_state(&mut self, id: u32, state: TxState) -> io::Result<()>;
+
+    ///Remove and return the oldest transaction if it has outlived the store's
+    ///retention as of `now`. Only stores with age based retention evict this way
+    fn pop_expired(&mut self, _now: Instant) -> Option<StoredTransaction> {
+        None
+    }
+
+    ///Write out anything the store has buffered
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    ///Returns every stored transaction, oldest first, so they can be saved in a
+    ///`Checkpoint`. Stores which already persist their transactions return `None`
+    fn retained(&self) -> Option<Vec<StoredTransaction>> {
+        None
+    }
+
+    ///Returns how much a store which persists its transactions has stored, including
+    ///anything still buffered, so a `Checkpoint` can check that it's restored onto the
+    ///same history. Stores kept in memory return `None`
+    fn persisted_len(&self) -> Option<u64> {
+        None
+    }
+
+    ///Discard everything a store which persists its transactions stored after it
+    ///reached `len`, as returned by `persisted_len`, so it can be returned to the
+    ///state a `Checkpoint` was taken in. Stores kept in memory don't support this
+    fn rollback(&mut self, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this transaction store can't be rolled back",
+        ))
+    }
+}
+
+/// A first-in-first-out buffer of processed transactions, which also keeps an
+/// index from transaction ID to position in the buffer
+///
+/// Like `RingBuffer`, the oldest transactions are dropped once they fall outside
+/// the buffer's `Retention`, but lookups by ID go through the index instead of scanning
+/// the buffer, so pushing, evicting, and looking up a transaction are all constant time.
+pub struct IndexedBuffer {
+    inside: VecDeque<StoredTransaction>,
+    ///When each transaction was pushed, only tracked for age based retention
+    pushed_at: VecDeque<Instant>,
+    ///Maps a transaction ID to the sequence number it was pushed with
+    index: HashMap<u32, u64>,
+    ///The sequence number of the transaction at the front of the buffer
+    front_seq: u64,
+    retention: Retention,
+}
+
+impl IndexedBuffer {
+    ///Create a new `IndexedBuffer` with a capacity
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_retention(Retention::Count(capacity))
+    }
+
+    ///Create a new `IndexedBuffer` which retains transactions according to `retention`
+    pub fn with_retention(retention: Retention) -> Self {
+        //Only preallocate for a fixed capacity, and not an unreasonably large one
+        let capacity = match retention {
+            Retention::Count(capacity) => capacity.min(DEFAULT_CAPACITY * 10),
+            _ => 0,
+        };
+        Self {
+            inside: VecDeque::with_capacity(capacity),
+            pushed_at: VecDeque::new(),
+            index: HashMap::with_capacity(capacity),
+            front_seq: 0,
+            retention,
+        }
+    }
+
+    ///Returns the retention of the buffer
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    ///Push a new transaction into the buffer, removing and returning the
+    ///oldest transaction if the buffer is full
+    ///
+    ///A buffer with a capacity of zero can't hold anything, so the
+    ///pushed transaction is returned straight back
+    pub fn push(&mut self, stored: StoredTransaction) -> Option<StoredTransaction> {
+        //The time is only needed for age based retention, so avoid looking it up otherwise
+        let now = matches!(self.retention, Retention::Age(_)).then(Instant::now);
+        self.push_inner(stored, now)
+    }
+
+    ///Push a new transaction into the buffer as of the time `now`, removing and
+    ///returning the oldest transaction if the buffer is full
+    pub fn push_at(
+        &mut self,
+        stored: StoredTransaction,
+        now: Instant,
+    ) -> Option<StoredTransaction> {
+        self.push_inner(stored, Some(now))
+    }
+
+    fn push_inner(
+        &mut self,
+        stored: StoredTransaction,
+        now: Option<Instant>,
+    ) -> Option<StoredTransaction> {
+        let mut evicted = None;
+        match self.retention {
+            Retention::Count(0) => return Some(stored),
+            Retention::Count(capacity) if self.inside.len() >= capacity => evicted = self.pop(),
+            Retention::Age(_) => self.pushed_at.push_back(now.unwrap_or_else(Instant::now)),
+            _ => {}
+        }
+
+        //Sequence numbers are never reused, so the position of a transaction in the
+        //buffer is always its sequence number minus that of the front of the buffer
+        let seq = self.front_seq + self.inside.len() as u64;
+        self.index.insert(stored.tx.id, seq);
+        self.inside.push_back(stored);
+        evicted
+    }
+
+    ///Pop the oldest transaction from the buffer
+    pub fn pop(&mut self) -> Option<StoredTransaction> {
+        let stored = self.inside.pop_front()?;
+        self.pushed_at.pop_front();
+
+        //Only remove the index entry if it still points at this transaction,
+        //as a newer transaction may have been pushed with the same ID
+        if self.index.get(&stored.tx.id) == Some(&self.front_seq) {
+            self.index.remove(&stored.tx.id);
+        }
+        self.front_seq += 1;
+        Some(stored)
+    }
+
+    ///Pop the oldest transaction from the buffer if it's older than the buffer's
+    ///retention as of the time `now`. Always `None` unless the retention is age based
+    pub fn pop_expired(&mut self, now: Instant) -> Option<StoredTransaction> {
+        let Retention::Age(max_age) = self.retention else {
+            return None;
+        };
+        let pushed_at = *self.pushed_at.front()?;
+        if now.saturating_duration_since(pushed_at) > max_age {
+            self.pop()
+        } else {
+            None
+        }
+    }
+
+    ///Returns the position in the buffer of a transaction by its ID
+    fn position(&self, id: u32) -> Option<usize> {
+        self.index
+            .get(&id)
+            .map(|seq| (seq - self.front_seq) as usize)
+    }
+
+    ///Get a transaction by its ID from the buffer
+    pub fn get_by_tx(&self, id: u32) -> Option<&StoredTransaction> {
+        self.position(id).map(|position| &self.inside[position])
+    }
+
+    ///Get a mutable reference to a transaction by its ID from the buffer,
+    ///so that its state can be updated
+    pub fn get_by_tx_mut(&mut self, id: u32) -> Option<&mut StoredTransaction> {
+        self.position(id).map(|position| &mut self.inside[position])
+    }
+
+    ///Returns the number of transactions in the buffer
+    pub fn len(&self) -> usize {
+        self.inside.len()
+    }
+
+    ///Returns whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.inside.is_empty()
+    }
+}
+
+impl TransactionStore for IndexedBuffer {
+    fn get(&self, id: u32) -> io::Result<Option<StoredTransaction>> {
+        Ok(self.get_by_tx(id).cloned())
+    }
+
+    fn push(&mut self, stored: StoredTransaction) -> io::Result<Option<StoredTransaction>> {
+        Ok(IndexedBuffer::push(self, stored))
+    }
+
+    fn set_state(&mut self, id: u32, state: TxState) -> io::Result<()> {
+        if let Some(stored) = self.get_by_tx_mut(id) {
+            stored.state = state;
+        }
+        Ok(())
+    }
+
+    fn pop_expired(&mut self, now: Instant) -> Option<StoredTransaction> {
+        IndexedBuffer::pop_expired(self, now)
+    }
+
+    ///Returns the buffer's transactions. Their ages aren't kept, so with age based
+    ///retention, restoring them treats them all as having just been processed
+    fn retained(&self) -> Option<Vec<StoredTransaction>> {
+        Some(self.inside.iter().cloned().collect())
+    }
+}
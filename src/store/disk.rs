@@ -0,0 +1,308 @@
+use crate::datatypes::{Amount, StoredTransaction, Transaction, TransactionType, TxState};
+use crate::store::TransactionStore;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The size of a transaction record in the data file
+const RECORD_SIZE: usize = 16;
+/// The size of an entry in the index file
+const INDEX_ENTRY_SIZE: u64 = 8;
+/// Appended records are buffered in memory until there are this many bytes of them
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+/// Stands in for a missing amount in a record. Only deposits and withdrawals are
+/// stored, which always have an amount, but this keeps the format total
+const NO_AMOUNT: i64 = i64::MIN;
+
+/// A transaction store kept in two files in a directory, so that every processed
+/// transaction stays available for disputes without being held in memory
+///
+/// `transactions.dat` is an append-only log of fixed size records, one per
+/// processed transaction. Changing a transaction's state appends a new version
+/// of its record rather than overwriting the old one.
+///
+/// `transactions.idx` maps each transaction ID to the offset of the latest version
+/// of its record. It's addressed directly by ID, with the 8 byte entry for ID `n` at
+/// offset `n * 8`, so a lookup is a single read. IDs which were never stored read as
+/// zero, which is why entries hold the record offset plus one. The file is sparse, so
+/// only the ranges of IDs which are actually used take up space on disk.
+///
+/// Recently appended records and index entries are buffered in memory and written
+/// out in batches, or when the store is flushed or dropped. A store opened with
+/// `open_read_only` never writes them out, so the files are left untouched.
+pub struct DiskStore {
+    data: File,
+    index: File,
+    ///The length of the data file, not including records which haven't been flushed
+    flushed_len: u64,
+    ///Records which have been appended but not yet written to the data file
+    pending: Vec<u8>,
+    ///Index entries which haven't been written to the index file yet
+    pending_index: HashMap<u32, u64>,
+    ///Whether the files are only read, with new records kept in memory instead
+    read_only: bool,
+}
+
+impl DiskStore {
+    ///Create a new, empty store in `dir`, replacing any transactions already stored there
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        Self::open_with(dir, true)
+    }
+
+    ///Open the store in `dir`, keeping any transactions already stored there,
+    ///or create a new one if it doesn't exist
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        Self::open_with(dir, false)
+    }
+
+    ///Open the existing store in `dir` without ever writing to it. Transactions
+    ///stored from then on are kept in memory, and are gone once the store is dropped
+    pub fn open_read_only(dir: &Path) -> io::Result<Self> {
+        let open = |name: &str| File::open(dir.join(name));
+        Self::with_files(open("transactions.dat")?, open("transactions.idx")?, true)
+    }
+
+    fn open_with(dir: &Path, truncate: bool) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let open = |name: &str| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(truncate)
+                .open(dir.join(name))
+        };
+        Self::with_files(open("transactions.dat")?, open("transactions.idx")?, false)
+    }
+
+    fn with_files(data: File, index: File, read_only: bool) -> io::Result<Self> {
+        //A record which was only partially written, e.g. if the process was killed
+        //mid-write, is ignored and will be overwritten by the next record
+        let len = data.metadata()?.len();
+        let flushed_len = len - len % RECORD_SIZE as u64;
+
+        Ok(Self {
+            data,
+            index,
+            flushed_len,
+            pending: Vec::with_capacity(FLUSH_THRESHOLD),
+            pending_index: HashMap::new(),
+            read_only,
+        })
+    }
+
+    ///Append a new version of a transaction's record and point the index at it
+    fn append(&mut self, stored: &StoredTransaction) -> io::Result<()> {
+        let offset = self.flushed_len + self.pending.len() as u64;
+        self.pending.extend_from_slice(&encode(stored));
+        self.pending_index.insert(stored.tx.id, offset);
+
+        if self.pending.len() >= FLUSH_THRESHOLD {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    ///Returns the offset of the latest record of a transaction, if it has been stored
+    fn offset(&self, id: u32) -> io::Result<Option<u64>> {
+        if let Some(offset) = self.pending_index.get(&id) {
+            return Ok(Some(*offset));
+        }
+
+        let mut entry = [0; INDEX_ENTRY_SIZE as usize];
+        let mut index = &self.index;
+        index.seek(SeekFrom::Start(u64::from(id) * INDEX_ENTRY_SIZE))?;
+        //Reading past the end of the index means the ID was never stored
+        let read = read_fully(&mut index, &mut entry)?;
+        if read < entry.len() {
+            return Ok(None);
+        }
+        Ok(u64::from_le_bytes(entry).checked_sub(1))
+    }
+
+    ///Write out the buffered index entries
+    fn write_index(&mut self) -> io::Result<()> {
+        //Write the index entries in ID order to keep the writes as sequential as possible
+        let mut entries: Vec<(u32, u64)> = self.pending_index.drain().collect();
+        entries.sort_unstable();
+        for (id, offset) in entries {
+            self.index
+                .seek(SeekFrom::Start(u64::from(id) * INDEX_ENTRY_SIZE))?;
+            self.index.write_all(&(offset + 1).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    ///Read the record at an offset, which may not have been flushed yet
+    fn read_record(&self, offset: u64) -> io::Result<StoredTransaction> {
+        let mut record = [0; RECORD_SIZE];
+        if offset >= self.flushed_len {
+            let start = (offset - self.flushed_len) as usize;
+            record.copy_from_slice(&self.pending[start..start + RECORD_SIZE]);
+        } else {
+            let mut data = &self.data;
+            data.seek(SeekFrom::Start(offset))?;
+            data.read_exact(&mut record)?;
+        }
+        decode(&record)
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn get(&self, id: u32) -> io::Result<Option<StoredTransaction>> {
+        match self.offset(id)? {
+            Some(offset) => self.read_record(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///Appends the transaction to the data file. Nothing is ever evicted
+    fn push(&mut self, stored: StoredTransaction) -> io::Result<Option<StoredTransaction>> {
+        self.append(&stored)?;
+        Ok(None)
+    }
+
+    fn set_state(&mut self, id: u32, state: TxState) -> io::Result<()> {
+        if let Some(mut stored) = self.get(id)? {
+            stored.state = state;
+            self.append(&stored)?;
+        }
+        Ok(())
+    }
+
+    ///Returns the length of the data file, including records which haven't been flushed
+    fn persisted_len(&self) -> Option<u64> {
+        Some(self.flushed_len + self.pending.len() as u64)
+    }
+
+    ///Writes out any buffered records, followed by the index entries pointing at them
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() || self.read_only {
+            return Ok(());
+        }
+
+        self.data.seek(SeekFrom::Start(self.flushed_len))?;
+        self.data.write_all(&self.pending)?;
+        self.flushed_len += self.pending.len() as u64;
+        self.pending.clear();
+        self.write_index()
+    }
+
+    ///Truncates the data file to `len`, then rebuilds the index from what's left,
+    ///since later versions of a record may have been cut off
+    fn rollback(&mut self, len: u64) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the store has transactions from after the checkpoint, \
+                 which can't be rolled back while it's opened read only",
+            ));
+        }
+        self.flush()?;
+        if len > self.flushed_len || !len.is_multiple_of(RECORD_SIZE as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't roll back to a position which isn't in the store",
+            ));
+        }
+        self.data.set_len(len)?;
+        self.flushed_len = len;
+        self.index.set_len(0)?;
+
+        //Later versions of a record overwrite earlier ones in the index
+        let mut data = BufReader::new(&self.data);
+        data.seek(SeekFrom::Start(0))?;
+        let mut record = [0; RECORD_SIZE];
+        for offset in (0..len).step_by(RECORD_SIZE) {
+            data.read_exact(&mut record)?;
+            let stored = decode(&record)?;
+            self.pending_index.insert(stored.tx.id, offset);
+        }
+        self.write_index()
+    }
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        //Errors can't be reported from drop, call flush() first to handle them
+        let _ = self.flush();
+    }
+}
+
+///Like `read_exact`, but returns how many bytes were read instead of
+///erroring if the end of the file is reached first
+fn read_fully(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+///Encode a transaction as a record: its type, state, client, ID, and amount,
+///with the integers in little endian
+fn encode(stored: &StoredTransaction) -> [u8; RECORD_SIZE] {
+    let tx = &stored.tx;
+    let tx_type = match tx.tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+    };
+    let state = match stored.state {
+        TxState::Processed => 0,
+        TxState::Disputed => 1,
+        TxState::Resolved => 2,
+        TxState::ChargedBack => 3,
+    };
+    let amount = tx.amount.map_or(NO_AMOUNT, Amount::raw);
+
+    let mut record = [0; RECORD_SIZE];
+    record[0] = tx_type;
+    record[1] = state;
+    record[2..4].copy_from_slice(&tx.client.to_le_bytes());
+    record[4..8].copy_from_slice(&tx.id.to_le_bytes());
+    record[8..16].copy_from_slice(&amount.to_le_bytes());
+    record
+}
+
+///Decode a record written by `encode`
+fn decode(record: &[u8; RECORD_SIZE]) -> io::Result<StoredTransaction> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt transaction record");
+
+    let tx_type = match record[0] {
+        0 => TransactionType::Deposit,
+        1 => TransactionType::Withdrawal,
+        2 => TransactionType::Dispute,
+        3 => TransactionType::Resolve,
+        4 => TransactionType::Chargeback,
+        _ => return Err(corrupt()),
+    };
+    let state = match record[1] {
+        0 => TxState::Processed,
+        1 => TxState::Disputed,
+        2 => TxState::Resolved,
+        3 => TxState::ChargedBack,
+        _ => return Err(corrupt()),
+    };
+    let client = u16::from_le_bytes([record[2], record[3]]);
+    let id = u32::from_le_bytes(record[4..8].try_into().unwrap());
+    let amount = i64::from_le_bytes(record[8..16].try_into().unwrap());
+
+    Ok(StoredTransaction {
+        tx: Transaction {
+            tx_type,
+            client,
+            id,
+            amount: (amount != NO_AMOUNT).then(|| Amount::from_raw(amount)),
+        },
+        state,
+    })
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use transaction_processor::datatypes::TransactionType;
+use transaction_processor::error::ProcessError;
+use transaction_processor::Ledger;
+
+/// The names of the transaction types, in the order they're counted in
+const TYPE_NAMES: [&str; 5] = ["deposit", "withdrawal", "dispute", "resolve", "chargeback"];
+
+/// Statistics about a run, reported once all inputs have been processed
+pub struct Summary {
+    started: Instant,
+    elapsed: Duration,
+    rows: u64,
+    ///Applied and rejected transactions, counted per transaction type
+    applied: [u64; 5],
+    rejected: [u64; 5],
+    ///Rejected rows which couldn't be parsed, so have no transaction type
+    unparsed: u64,
+    ///Rejected rows counted per error code
+    rejections: BTreeMap<&'static str, u64>,
+    clients: usize,
+    locked: usize,
+    open_disputes: usize,
+}
+
+impl Summary {
+    ///Start timing a run
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            elapsed: Duration::ZERO,
+            rows: 0,
+            applied: [0; 5],
+            rejected: [0; 5],
+            unparsed: 0,
+            rejections: BTreeMap::new(),
+            clients: 0,
+            locked: 0,
+            open_disputes: 0,
+        }
+    }
+
+    ///Count a transaction which was applied
+    pub fn applied(&mut self, tx_type: &TransactionType) {
+        self.rows += 1;
+        self.applied[type_index(tx_type)] += 1;
+    }
+
+    ///Count a row which was rejected
+    pub fn rejected(&mut self, e: &ProcessError) {
+        self.rows += 1;
+        match e.transaction() {
+            Some(tx) => self.rejected[type_index(&tx.tx_type)] += 1,
+            None => self.unparsed += 1,
+        }
+        *self.rejections.entry(e.code()).or_default() += 1;
+    }
+
+    ///Stop timing the run and take the final counts from the ledger
+    pub fn finish(&mut self, ledger: &Ledger) {
+        self.elapsed = self.started.elapsed();
+        self.clients = ledger.clients().count();
+        self.locked = ledger.clients().filter(|client| client.locked).count();
+        self.open_disputes = ledger.open_disputes();
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        //Avoid dividing by zero for a run too short to measure
+        let throughput = if seconds > 0.0 {
+            self.rows as f64 / seconds
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "Processed {} row(s) in {seconds:.3}s ({throughput:.0} rows/s)",
+            self.rows
+        )?;
+
+        writeln!(f, "{:<12}{:>12}{:>12}", "type", "applied", "rejected")?;
+        for (i, name) in TYPE_NAMES.iter().enumerate() {
+            writeln!(
+                f,
+                "{name:<12}{:>12}{:>12}",
+                self.applied[i], self.rejected[i]
+            )?;
+        }
+        if self.unparsed > 0 {
+            writeln!(f, "{:<12}{:>12}{:>12}", "unparsed", "", self.unparsed)?;
+        }
+
+        if !self.rejections.is_empty() {
+            writeln!(f, "Rejections:")?;
+            for (code, count) in &self.rejections {
+                writeln!(f, "  {code}: {count}")?;
+            }
+        }
+
+        writeln!(f, "Clients: {} ({} locked)", self.clients, self.locked)?;
+        write!(f, "Open disputes: {}", self.open_disputes)
+    }
+}
+
+///The position of a transaction type in `TYPE_NAMES`
+fn type_index(tx_type: &TransactionType) -> usize {
+    match tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+    }
+}
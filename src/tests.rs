@@ -1,6 +1,18 @@
-use crate::datatypes::{RingBuffer, Transaction, TransactionType};
-use crate::process_transaction;
-use std::collections::HashMap;
+use crate::datatypes::{
+    Amount, RingBuffer, StoredTransaction, Transaction, TransactionRecord, TransactionType,
+    TxIdSet, TxState,
+};
+use crate::error::{CheckpointError, ErrorCategory, ProcessError, SnapshotError};
+use crate::store::{DiskStore, IndexedBuffer, TransactionStore};
+use crate::validate;
+use crate::{
+    Checkpoint, DedupPolicy, DisputePolicy, Journal, Ledger, LedgerConfig, OutputOrder, Retention,
+};
+
+///Parse an amount from a string, for brevity in tests
+fn amt(s: &str) -> Amount {
+    s.parse().unwrap()
+}
 
 ///RingBuffer should allow pushing as many items as its capacity
 ///and popping them in the order they were pushed, dropping the oldest
@@ -19,26 +31,24 @@ fn test_ring_buffer() {
     assert_eq!(buffer.pop(), None);
 }
 
-///Test the get_by_tx function
+///Test looking up retained transactions by ID
 #[test]
 fn test_get_by_tx() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let transactions = (1..=20).map(|i| Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: i,
-        amount: Some(i as f64),
+        amount: Some(Amount::from_raw(i as i64 * Amount::SCALE)),
     });
 
     for tx in transactions {
-        process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+        ledger.apply(tx).unwrap();
     }
 
-    let tx = processed_txs.get_by_tx(18).unwrap();
-    assert_eq!(tx.amount.unwrap(), 18.0);
+    let tx = ledger.transaction(18).unwrap();
+    assert_eq!(tx.amount.unwrap(), amt("18"));
 }
 
 ///Test that deposits behave correctly
@@ -46,22 +56,20 @@ fn test_get_by_tx() {
 ///Deposits should increase the client's available and total funds
 #[test]
 fn test_deposit() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 1,
-        amount: Some(20.1234),
+        amount: Some(amt("20.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 20.1234);
-    assert_eq!(client.total, 20.1234);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("20.1234"));
+    assert_eq!(client.total, amt("20.1234"));
 }
 
 ///Test that withdrawals behave correctly
@@ -70,50 +78,51 @@ fn test_deposit() {
 ///be allowed if the client has insufficient available funds
 #[test]
 fn test_withdrawal() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 1,
-        amount: Some(20.1234),
+        amount: Some(amt("20.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 20.1234);
-    assert_eq!(client.total, 20.1234);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("20.1234"));
+    assert_eq!(client.total, amt("20.1234"));
 
     let tx = Transaction {
         tx_type: TransactionType::Withdrawal,
         client: 1,
         id: 2,
-        amount: Some(10.1234),
+        amount: Some(amt("10.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 10.0);
-    assert_eq!(client.total, 10.0);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("10.0"));
+    assert_eq!(client.total, amt("10.0"));
 
     let tx = Transaction {
         tx_type: TransactionType::Withdrawal,
         client: 1,
         id: 3,
-        amount: Some(20.0),
+        amount: Some(amt("20.0")),
     };
 
-    let result = process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs);
+    let result = ledger.apply(tx);
 
     //assert that the withdrawal fails and the client's funds are unchanged
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 10.0);
-    assert_eq!(client.total, 10.0);
-    assert_eq!(result.is_err(), true);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("10.0"));
+    assert_eq!(client.total, amt("10.0"));
+    assert!(matches!(
+        result,
+        Err(ProcessError::InsufficientFunds { .. })
+    ));
 }
 
 ///Test that disputes behave correctly
@@ -121,27 +130,25 @@ fn test_withdrawal() {
 ///Disputes should move the disputed transaction's amount from available to held funds
 #[test]
 fn test_dispute() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 1,
-        amount: Some(20.1234),
+        amount: Some(amt("20.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 2,
-        amount: Some(10.0),
+        amount: Some(amt("10.0")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Dispute,
@@ -150,18 +157,18 @@ fn test_dispute() {
         amount: None,
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     //The client's available funds should be decreased by the amount of the disputed transaction
     //and the held funds should be increased by the amount of the disputed transaction
     //and the total funds should be unchanged
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 20.1234);
-    assert_eq!(client.held, 10.0);
-    assert_eq!(client.total, 30.1234);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("20.1234"));
+    assert_eq!(client.held, amt("10.0"));
+    assert_eq!(client.total, amt("30.1234"));
 
     //The disputed transaction should be in the held_txs hashmap
-    assert_eq!(held_txs.contains_key(&2), true);
+    assert!(ledger.dispute(2).is_some());
 }
 
 ///Test that resolves behave correctly
@@ -170,27 +177,25 @@ fn test_dispute() {
 ///and remove the transaction from the held_txs hashmap
 #[test]
 fn test_resolve() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 1,
-        amount: Some(20.1234),
+        amount: Some(amt("20.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 2,
-        amount: Some(10.0),
+        amount: Some(amt("10.0")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Dispute,
@@ -199,7 +204,7 @@ fn test_resolve() {
         amount: None,
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Resolve,
@@ -208,18 +213,18 @@ fn test_resolve() {
         amount: None,
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     //The client's available funds should be increased by the amount of the disputed transaction
     //and the held funds should be decreased by the amount of the disputed transaction
     //and the total funds should be unchanged
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 30.1234);
-    assert_eq!(client.held, 0.0);
-    assert_eq!(client.total, 30.1234);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("30.1234"));
+    assert_eq!(client.held, amt("0.0"));
+    assert_eq!(client.total, amt("30.1234"));
 
     //The disputed transaction should be removed from the held_txs hashmap
-    assert_eq!(held_txs.contains_key(&2), false);
+    assert!(ledger.dispute(2).is_none());
 }
 
 ///Test that chargebacks behave correctly
@@ -228,27 +233,25 @@ fn test_resolve() {
 ///client's held and total funds
 #[test]
 fn test_chargeback() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 1,
-        amount: Some(20.1234),
+        amount: Some(amt("20.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 2,
-        amount: Some(10.0),
+        amount: Some(amt("10.0")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Dispute,
@@ -257,7 +260,7 @@ fn test_chargeback() {
         amount: None,
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Chargeback,
@@ -266,28 +269,26 @@ fn test_chargeback() {
         amount: None,
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     //The client's held funds should be decreased by the amount of the disputed transaction
     //and the total funds should be decreased by the amount of the disputed transaction
     //and the available funds should be unchanged
     //and the client should be locked after a chargeback
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.available, 20.1234);
-    assert_eq!(client.held, 0.0);
-    assert_eq!(client.total, 20.1234);
-    assert_eq!(client.locked, true);
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("20.1234"));
+    assert_eq!(client.held, amt("0.0"));
+    assert_eq!(client.total, amt("20.1234"));
+    assert!(client.locked);
 
     //The disputed transaction should be removed from the held_txs hashmap
-    assert_eq!(held_txs.contains_key(&2), false);
+    assert!(ledger.dispute(2).is_none());
 }
 
 ///Test that a dispute on a transaction that doesn't exist errors, but doesn't affect anything
 #[test]
 fn test_dispute_nonexistent() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Dispute,
@@ -296,19 +297,20 @@ fn test_dispute_nonexistent() {
         amount: None,
     };
 
-    let result = process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs);
-    assert_eq!(result.is_err(), true);
-    assert_eq!(processed_txs.is_empty(), true);
-    assert_eq!(clients.contains_key(&1), false);
-    assert_eq!(held_txs.is_empty(), true);
+    let result = ledger.apply(tx);
+    assert!(matches!(
+        result,
+        Err(ProcessError::UnknownTransaction { .. })
+    ));
+    assert!(ledger.transaction(1).is_none());
+    assert!(ledger.client(1).is_none());
+    assert!(ledger.dispute(1).is_none());
 }
 
 ///Test that resolve transaction which doesn't exist errors, but doesn't affect anything
 #[test]
 fn test_resolve_nonexistent() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Resolve,
@@ -317,18 +319,16 @@ fn test_resolve_nonexistent() {
         amount: None,
     };
 
-    let result = process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs);
-    assert_eq!(result.is_err(), true);
-    assert_eq!(processed_txs.is_empty(), true);
-    assert_eq!(clients.contains_key(&1), false);
+    let result = ledger.apply(tx);
+    assert!(matches!(result, Err(ProcessError::UnknownDispute { .. })));
+    assert!(ledger.transaction(1).is_none());
+    assert!(ledger.client(1).is_none());
 }
 
 ///Test that chargeback transaction which doesn't exist errors, but doesn't affect anything
 #[test]
 fn test_chargeback_nonexistent() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Chargeback,
@@ -337,37 +337,1309 @@ fn test_chargeback_nonexistent() {
         amount: None,
     };
 
-    let result = process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs);
-    assert_eq!(result.is_err(), true);
-    assert_eq!(processed_txs.is_empty(), true);
-    assert_eq!(clients.contains_key(&1), false);
+    let result = ledger.apply(tx);
+    assert!(matches!(result, Err(ProcessError::UnknownDispute { .. })));
+    assert!(ledger.transaction(1).is_none());
+    assert!(ledger.client(1).is_none());
 }
 
-///Test that client amounts are rounded to 4 decimal places
+///Test that client amounts are kept exactly to 4 decimal places
 #[test]
 fn test_rounding() {
-    let mut clients = HashMap::new();
-    let mut processed_txs = RingBuffer::with_capacity(10);
-    let mut held_txs = HashMap::new();
+    let mut ledger = Ledger::with_capacity(10);
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 1,
-        amount: Some(20.1234),
+        amount: Some(amt("20.1234")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    ledger.apply(tx).unwrap();
 
     let tx = Transaction {
         tx_type: TransactionType::Deposit,
         client: 1,
         id: 2,
-        amount: Some(1.0007),
+        amount: Some(amt("1.0007")),
+    };
+
+    ledger.apply(tx).unwrap();
+
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.total, amt("21.1241"));
+}
+
+///Amounts should parse exactly from decimal strings and format back with
+///trailing zeros trimmed, and reject anything that isn't a plain decimal
+///with at most 4 decimal places
+#[test]
+fn test_amount_parse_and_display() {
+    assert_eq!(amt("1.5"), Amount::from_raw(15_000));
+    assert_eq!(amt("20"), Amount::from_raw(200_000));
+    assert_eq!(amt(".0001"), Amount::from_raw(1));
+    assert_eq!(amt("-0.0001"), Amount::from_raw(-1));
+    assert_eq!(amt("20.0000"), amt("20"));
+
+    assert_eq!(amt("2").to_string(), "2.0");
+    assert_eq!(amt("1.50").to_string(), "1.5");
+    assert_eq!(amt("0.1234").to_string(), "0.1234");
+    assert_eq!(amt("-0.0001").to_string(), "-0.0001");
+    //Debug output, as used in error messages, is the same decimal
+    assert_eq!(format!("{:?}", Some(amt("40"))), "Some(40.0)");
+
+    for invalid in [
+        "",
+        ".",
+        "-",
+        "1.23456",
+        "NaN",
+        "inf",
+        "1e5",
+        "1,5",
+        "99999999999999999",
+    ] {
+        assert!(
+            invalid.parse::<Amount>().is_err(),
+            "{invalid:?} should not parse"
+        );
+    }
+}
+
+///Repeatedly depositing and withdrawing an amount that isn't representable
+///as an `f64` should leave balances exact
+#[test]
+fn test_amount_exact_over_many_transactions() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    for i in 0..1000 {
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id: i,
+            amount: Some(amt("0.1")),
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("100"));
+    assert_eq!(client.total, amt("100"));
+}
+
+///A deposit that would overflow the client's funds should error and leave the client unchanged
+#[test]
+fn test_deposit_overflow() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    let tx = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 1,
+        amount: Some(Amount::from_raw(i64::MAX)),
+    };
+
+    ledger.apply(tx).unwrap();
+
+    let tx = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 2,
+        amount: Some(amt("1")),
+    };
+
+    let result = ledger.apply(tx);
+    assert!(matches!(result, Err(ProcessError::Overflow { .. })));
+
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, Amount::from_raw(i64::MAX));
+    assert_eq!(client.total, Amount::from_raw(i64::MAX));
+}
+
+///Errors should carry the offending transaction, and so the client involved
+#[test]
+fn test_error_carries_transaction() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    let tx = Transaction {
+        tx_type: TransactionType::Withdrawal,
+        client: 7,
+        id: 3,
+        amount: None,
+    };
+
+    let result = ledger.apply(tx);
+    match result {
+        Err(ProcessError::MissingAmount { tx }) => {
+            assert_eq!(tx.client, 7);
+            assert_eq!(tx.id, 3);
+        }
+        other => panic!("expected MissingAmount, got {other:?}"),
+    }
+}
+
+///A locked client's transactions should be rejected with a LockedAccount error
+#[test]
+fn test_locked_account_error() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    let txs = [
+        (TransactionType::Deposit, 1, Some(amt("10"))),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Chargeback, 1, None),
+    ];
+    for (tx_type, id, amount) in txs {
+        let tx = Transaction {
+            tx_type,
+            client: 1,
+            id,
+            amount,
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    let tx = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 2,
+        amount: Some(amt("5")),
+    };
+
+    let result = ledger.apply(tx);
+    assert!(matches!(result, Err(ProcessError::LockedAccount { .. })));
+}
+
+///The ledger should expose every client it has created
+#[test]
+fn test_ledger_clients() {
+    let mut ledger = Ledger::new();
+
+    for (client, id) in [(1, 1), (2, 2), (1, 3), (3, 4)] {
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client,
+            id,
+            amount: Some(amt("1")),
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    let mut ids: Vec<u16> = ledger.clients().map(|client| client.client).collect();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(ledger.client(1).unwrap().total, amt("2"));
+}
+
+///TxIdSet should only contain the IDs inserted into it, across pages
+#[test]
+fn test_tx_id_set() {
+    let mut set = TxIdSet::new();
+
+    assert!(set.insert(1));
+    assert!(set.insert(70_000));
+    assert!(set.insert(u32::MAX));
+    assert!(!set.insert(1));
+
+    assert!(set.contains(1));
+    assert!(set.contains(70_000));
+    assert!(set.contains(u32::MAX));
+    assert!(!set.contains(0));
+    assert!(!set.contains(2));
+    assert!(!set.contains(70_001));
+}
+
+///A deposit or withdrawal reusing an ID should be rejected without affecting the client
+#[test]
+fn test_duplicate_transaction() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    let deposit = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 1,
+        amount: Some(amt("10")),
+    };
+
+    ledger.apply(deposit.clone()).unwrap();
+    let result = ledger.apply(deposit);
+    assert!(matches!(
+        result,
+        Err(ProcessError::DuplicateTransaction { .. })
+    ));
+
+    let withdrawal = Transaction {
+        tx_type: TransactionType::Withdrawal,
+        client: 1,
+        id: 1,
+        amount: Some(amt("5")),
+    };
+
+    let result = ledger.apply(withdrawal);
+    assert!(matches!(
+        result,
+        Err(ProcessError::DuplicateTransaction { .. })
+    ));
+
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("10"));
+    assert_eq!(client.total, amt("10"));
+}
+
+///With a windowed dedup policy, an ID can only be reused once its original
+///transaction is no longer retained, whereas the all-time policy always rejects it
+#[test]
+fn test_duplicate_transaction_horizon() {
+    for (dedup, reuse_allowed) in [(DedupPolicy::Window, true), (DedupPolicy::AllTime, false)] {
+        let mut ledger = Ledger::with_config(LedgerConfig {
+            retention: Retention::Count(2),
+            dedup,
+            ..LedgerConfig::default()
+        });
+
+        for id in 1..=3 {
+            let tx = Transaction {
+                tx_type: TransactionType::Deposit,
+                client: 1,
+                id,
+                amount: Some(amt("1")),
+            };
+            ledger.apply(tx).unwrap();
+        }
+
+        //Transaction 1 has been evicted from the retention window
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id: 1,
+            amount: Some(amt("1")),
+        };
+        assert_eq!(ledger.apply(tx).is_ok(), reuse_allowed, "{dedup:?}");
+
+        //Transaction 3 is still retained, so it's a duplicate under both policies
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id: 3,
+            amount: Some(amt("1")),
+        };
+        let result = ledger.apply(tx);
+        assert!(matches!(
+            result,
+            Err(ProcessError::DuplicateTransaction { .. })
+        ));
+    }
+}
+
+///With a windowed dedup policy, a disputed transaction which has been evicted
+///is still a duplicate until its dispute is settled, so the settlement can't
+///land on a new transaction reusing its ID
+#[test]
+fn test_duplicate_of_evicted_dispute() {
+    let mut ledger = Ledger::with_config(LedgerConfig {
+        retention: Retention::Count(1),
+        dedup: DedupPolicy::Window,
+        ..LedgerConfig::default()
+    });
+    ledger.apply(stored_deposit(1, "5.0").tx).unwrap();
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    //Evicts transaction 1 from the retention window while it's disputed
+    ledger.apply(stored_deposit(2, "1.0").tx).unwrap();
+
+    let e = ledger.apply(stored_deposit(1, "3.0").tx).unwrap_err();
+    assert!(matches!(e, ProcessError::DuplicateTransaction { .. }));
+
+    //The resolve settles the original dispute, after which the ID can be reused
+    apply_action(&mut ledger, TransactionType::Resolve, 1).unwrap();
+    assert_funds(&ledger, "6.0", "0.0", "6.0");
+    ledger.apply(stored_deposit(1, "3.0").tx).unwrap();
+    assert_eq!(ledger.state(1), Some(TxState::Processed));
+}
+
+///A dispute, resolve, or chargeback from a different client than the one who made
+///the referenced transaction should be rejected without affecting either client
+#[test]
+fn test_client_mismatch() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    let tx = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 1,
+        amount: Some(amt("10")),
+    };
+    ledger.apply(tx).unwrap();
+
+    let tx = Transaction {
+        tx_type: TransactionType::Dispute,
+        client: 5,
+        id: 1,
+        amount: None,
+    };
+    let result = ledger.apply(tx);
+    assert!(matches!(
+        result,
+        Err(ProcessError::ClientMismatch { owner: 1, .. })
+    ));
+    assert!(ledger.dispute(1).is_none());
+
+    let tx = Transaction {
+        tx_type: TransactionType::Dispute,
+        client: 1,
+        id: 1,
+        amount: None,
+    };
+    ledger.apply(tx).unwrap();
+
+    for tx_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+        let tx = Transaction {
+            tx_type,
+            client: 5,
+            id: 1,
+            amount: None,
+        };
+        let result = ledger.apply(tx);
+        assert!(matches!(
+            result,
+            Err(ProcessError::ClientMismatch { owner: 1, .. })
+        ));
+    }
+
+    //The dispute should still be open and the funds still held
+    assert!(ledger.dispute(1).is_some());
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("0"));
+    assert_eq!(client.held, amt("10"));
+    assert!(!client.locked);
+    assert!(ledger.client(5).is_none());
+}
+
+///Deposits and withdrawals with zero, negative, or too large amounts should be rejected,
+///as should disputes, resolves, and chargebacks with an amount
+#[test]
+fn test_validate_amounts() {
+    let mut ledger = Ledger::with_config(LedgerConfig {
+        max_amount: Some(amt("1000")),
+        ..LedgerConfig::default()
+    });
+
+    let tx = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 1,
+        amount: Some(amt("100")),
+    };
+    ledger.apply(tx).unwrap();
+
+    for (tx_type, id, amount) in [
+        (TransactionType::Deposit, 2, "-50"),
+        (TransactionType::Deposit, 3, "0"),
+        (TransactionType::Withdrawal, 4, "-50"),
+    ] {
+        let tx = Transaction {
+            tx_type,
+            client: 1,
+            id,
+            amount: Some(amt(amount)),
+        };
+        let result = ledger.apply(tx);
+        assert!(matches!(
+            result,
+            Err(ProcessError::NonPositiveAmount { .. })
+        ));
+    }
+
+    let tx = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id: 5,
+        amount: Some(amt("1000.0001")),
+    };
+    let result = ledger.apply(tx);
+    assert!(matches!(result, Err(ProcessError::AmountTooLarge { .. })));
+
+    let tx = Transaction {
+        tx_type: TransactionType::Dispute,
+        client: 1,
+        id: 1,
+        amount: Some(amt("100")),
+    };
+    let result = ledger.apply(tx);
+    assert!(matches!(result, Err(ProcessError::UnexpectedAmount { .. })));
+
+    //None of the rejected transactions should have affected the client
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("100"));
+    assert_eq!(client.held, amt("0"));
+    assert_eq!(client.total, amt("100"));
+}
+
+///Amounts which aren't finite or have more than 4 decimal places
+///should be rejected when the row is deserialized
+#[test]
+fn test_deserialize_invalid_amounts() {
+    let input = "type, client, tx, amount
+deposit, 1, 1, NaN
+deposit, 1, 2, inf
+deposit, 1, 3, 1.00001
+deposit, 1, 4, 1.0001
+";
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes());
+    let results: Vec<_> = reader.deserialize::<Transaction>().collect();
+
+    assert!(results[0]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("not finite"));
+    assert!(results[1]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("not finite"));
+    assert!(results[2]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("more than 4 decimal places"));
+    assert_eq!(results[3].as_ref().unwrap().amount, Some(amt("1.0001")));
+}
+
+///Amounts which can't be represented should be rejected by validation with their
+///own codes, in the same category as any other malformed amount
+#[test]
+fn test_parse_invalid_amounts() {
+    let input = "type, client, tx, amount
+deposit, 1, 1, NaN
+deposit, 1, 2, inf
+deposit, 1, 3, 1.00001
+deposit, 1, 4, 1e5
+deposit, 1, 5, 1.0001
+dispute, 1, 5,
+";
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes());
+    let results: Vec<_> = reader
+        .deserialize::<TransactionRecord>()
+        .map(|record| validate::parse(record.unwrap()))
+        .collect();
+
+    let codes: Vec<_> = results[..4]
+        .iter()
+        .map(|result| result.as_ref().unwrap_err().code())
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            "non_finite_amount",
+            "non_finite_amount",
+            "too_precise_amount",
+            "invalid_amount"
+        ]
+    );
+    for result in &results[..4] {
+        let e = result.as_ref().unwrap_err();
+        assert_eq!(e.category(), ErrorCategory::Validation);
+        assert_eq!(e.transaction().unwrap().client, 1);
+    }
+    assert_eq!(results[4].as_ref().unwrap().amount, Some(amt("1.0001")));
+    assert_eq!(results[5].as_ref().unwrap().amount, None);
+}
+
+///Apply a transaction which refers back to transaction `id` of client 1,
+///for brevity in the lifecycle tests
+fn apply_action(
+    ledger: &mut Ledger,
+    tx_type: TransactionType,
+    id: u32,
+) -> Result<(), ProcessError> {
+    ledger.apply(Transaction {
+        tx_type,
+        client: 1,
+        id,
+        amount: None,
+    })
+}
+
+///A transaction can only be disputed once, and a resolved or charged back
+///transaction can't be disputed, resolved, or charged back again
+#[test]
+fn test_transaction_lifecycle() {
+    let mut ledger = Ledger::with_capacity(10);
+
+    for id in 1..=2 {
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id,
+            amount: Some(amt("10")),
+        };
+        ledger.apply(tx).unwrap();
+    }
+    assert_eq!(ledger.state(1), Some(TxState::Processed));
+
+    //Disputing twice should not hold the funds twice
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    assert_eq!(ledger.state(1), Some(TxState::Disputed));
+    let result = apply_action(&mut ledger, TransactionType::Dispute, 1);
+    assert!(matches!(
+        result,
+        Err(ProcessError::IllegalTransition {
+            state: TxState::Disputed,
+            ..
+        })
+    ));
+    assert_eq!(ledger.client(1).unwrap().held, amt("10"));
+
+    //A resolved transaction can't be disputed or resolved again
+    apply_action(&mut ledger, TransactionType::Resolve, 1).unwrap();
+    assert_eq!(ledger.state(1), Some(TxState::Resolved));
+    for tx_type in [TransactionType::Dispute, TransactionType::Resolve] {
+        let result = apply_action(&mut ledger, tx_type, 1);
+        assert!(matches!(
+            result,
+            Err(ProcessError::IllegalTransition {
+                state: TxState::Resolved,
+                ..
+            })
+        ));
+    }
+
+    //A charged back transaction can't be disputed again, even though it's still retained.
+    //The client is locked by the chargeback, but the state is checked first
+    apply_action(&mut ledger, TransactionType::Dispute, 2).unwrap();
+    apply_action(&mut ledger, TransactionType::Chargeback, 2).unwrap();
+    assert_eq!(ledger.state(2), Some(TxState::ChargedBack));
+    let result = apply_action(&mut ledger, TransactionType::Dispute, 2);
+    assert!(matches!(
+        result,
+        Err(ProcessError::IllegalTransition {
+            state: TxState::ChargedBack,
+            ..
+        })
+    ));
+
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("10"));
+    assert_eq!(client.held, amt("0"));
+    assert_eq!(client.total, amt("10"));
+}
+
+///Only disputes, resolves, and chargebacks in the right order are legal transitions
+#[test]
+fn test_tx_state_transitions() {
+    use TransactionType::*;
+
+    assert_eq!(
+        TxState::Processed.transition(&Dispute),
+        Some(TxState::Disputed)
+    );
+    assert_eq!(
+        TxState::Disputed.transition(&Resolve),
+        Some(TxState::Resolved)
+    );
+    assert_eq!(
+        TxState::Disputed.transition(&Chargeback),
+        Some(TxState::ChargedBack)
+    );
+
+    assert_eq!(TxState::Processed.transition(&Resolve), None);
+    assert_eq!(TxState::Processed.transition(&Chargeback), None);
+    assert_eq!(TxState::Disputed.transition(&Dispute), None);
+    assert_eq!(TxState::Resolved.transition(&Dispute), None);
+    assert_eq!(TxState::ChargedBack.transition(&Dispute), None);
+    assert_eq!(TxState::Processed.transition(&Deposit), None);
+}
+
+///Create a ledger with the given dispute policy, where client 1 deposited 100 (tx 1)
+///and then withdrew 40 (tx 2), and the withdrawal has been disputed
+fn disputed_withdrawal(disputes: DisputePolicy) -> Ledger {
+    let mut ledger = Ledger::with_config(LedgerConfig {
+        disputes,
+        ..LedgerConfig::default()
+    });
+
+    for (tx_type, id, amount) in [
+        (TransactionType::Deposit, 1, "100"),
+        (TransactionType::Withdrawal, 2, "40"),
+    ] {
+        let tx = Transaction {
+            tx_type,
+            client: 1,
+            id,
+            amount: Some(amt(amount)),
+        };
+        ledger.apply(tx).unwrap();
+    }
+    apply_action(&mut ledger, TransactionType::Dispute, 2).unwrap();
+    ledger
+}
+
+///Assert a client's available, held, and total funds
+fn assert_funds(ledger: &Ledger, available: &str, held: &str, total: &str) {
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt(available), "available");
+    assert_eq!(client.held, amt(held), "held");
+    assert_eq!(client.total, amt(total), "total");
+}
+
+///With the deposits only policy, disputing a withdrawal should be rejected
+#[test]
+fn test_dispute_policy_deposits_only() {
+    let mut ledger = Ledger::with_config(LedgerConfig {
+        disputes: DisputePolicy::DepositsOnly,
+        ..LedgerConfig::default()
+    });
+
+    for (tx_type, id, amount) in [
+        (TransactionType::Deposit, 1, "100"),
+        (TransactionType::Withdrawal, 2, "40"),
+    ] {
+        let tx = Transaction {
+            tx_type,
+            client: 1,
+            id,
+            amount: Some(amt(amount)),
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    let result = apply_action(&mut ledger, TransactionType::Dispute, 2);
+    assert!(matches!(result, Err(ProcessError::NotDisputable { .. })));
+    assert_funds(&ledger, "60", "0", "60");
+
+    //Deposits can still be disputed
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    assert_funds(&ledger, "-40", "100", "60");
+}
+
+///With deposit semantics, a disputed withdrawal moves its amount from available to
+///held, and a chargeback removes it from held and total
+#[test]
+fn test_dispute_policy_deposit_semantics() {
+    let mut ledger = disputed_withdrawal(DisputePolicy::DepositSemantics);
+    assert_funds(&ledger, "20", "40", "60");
+
+    let mut resolved = disputed_withdrawal(DisputePolicy::DepositSemantics);
+    apply_action(&mut resolved, TransactionType::Resolve, 2).unwrap();
+    assert_funds(&resolved, "60", "0", "60");
+
+    apply_action(&mut ledger, TransactionType::Chargeback, 2).unwrap();
+    assert_funds(&ledger, "20", "0", "20");
+    assert!(ledger.client(1).unwrap().locked);
+}
+
+///With reversed withdrawal semantics, a disputed withdrawal holds the withdrawn
+///amount as a potential credit. Resolving lets the withdrawal stand, while a
+///chargeback reverses it and credits the amount back to available funds
+#[test]
+fn test_dispute_policy_reverse_withdrawals() {
+    let mut ledger = disputed_withdrawal(DisputePolicy::ReverseWithdrawals);
+    assert_funds(&ledger, "60", "40", "100");
+
+    let mut resolved = disputed_withdrawal(DisputePolicy::ReverseWithdrawals);
+    apply_action(&mut resolved, TransactionType::Resolve, 2).unwrap();
+    assert_funds(&resolved, "60", "0", "60");
+
+    apply_action(&mut ledger, TransactionType::Chargeback, 2).unwrap();
+    assert_funds(&ledger, "100", "0", "100");
+    assert!(ledger.client(1).unwrap().locked);
+
+    //Deposits are still disputed with deposit semantics
+    let mut ledger = disputed_withdrawal(DisputePolicy::ReverseWithdrawals);
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    assert_funds(&ledger, "-40", "140", "100");
+}
+
+///Create a processed deposit with the given ID and amount, for the buffer tests
+fn stored_deposit(id: u32, amount: &str) -> StoredTransaction {
+    StoredTransaction::new(Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        id,
+        amount: Some(amt(amount)),
+    })
+}
+
+///IndexedBuffer should evict in FIFO order like RingBuffer, while keeping
+///lookups by ID consistent with what's still in the buffer
+#[test]
+fn test_indexed_buffer() {
+    let mut buffer = IndexedBuffer::with_capacity(3);
+
+    for id in 1..=3 {
+        assert!(buffer.push(stored_deposit(id, "1")).is_none());
+    }
+    let evicted = buffer.push(stored_deposit(4, "1")).unwrap();
+    assert_eq!(evicted.tx.id, 1);
+    assert_eq!(buffer.len(), 3);
+
+    assert!(buffer.get_by_tx(1).is_none());
+    for id in 2..=4 {
+        assert_eq!(buffer.get_by_tx(id).unwrap().tx.id, id);
+    }
+
+    buffer.get_by_tx_mut(3).unwrap().state = TxState::Disputed;
+    assert_eq!(buffer.get_by_tx(3).unwrap().state, TxState::Disputed);
+
+    assert_eq!(buffer.pop().unwrap().tx.id, 2);
+    assert_eq!(buffer.get_by_tx(4).unwrap().tx.id, 4);
+}
+
+///Evicting an older transaction shouldn't remove the index entry of a newer one with the same ID
+#[test]
+fn test_indexed_buffer_reused_id() {
+    let mut buffer = IndexedBuffer::with_capacity(2);
+
+    buffer.push(stored_deposit(1, "1"));
+    buffer.push(stored_deposit(1, "2"));
+    assert_eq!(buffer.get_by_tx(1).unwrap().tx.amount, Some(amt("2")));
+
+    buffer.push(stored_deposit(2, "3"));
+    assert_eq!(buffer.get_by_tx(1).unwrap().tx.amount, Some(amt("2")));
+}
+
+///A buffer with no capacity shouldn't retain anything
+#[test]
+fn test_indexed_buffer_zero_capacity() {
+    let mut buffer = IndexedBuffer::with_capacity(0);
+
+    let evicted = buffer.push(stored_deposit(1, "1")).unwrap();
+    assert_eq!(evicted.tx.id, 1);
+    assert!(buffer.is_empty());
+    assert!(buffer.get_by_tx(1).is_none());
+}
+
+///Retentions should parse from counts, ages with units, and `unbounded`
+#[test]
+fn test_retention_parse() {
+    use std::time::Duration;
+
+    assert_eq!("50000".parse(), Ok(Retention::Count(50000)));
+    assert_eq!("unbounded".parse(), Ok(Retention::Unbounded));
+    assert_eq!("90s".parse(), Ok(Retention::Age(Duration::from_secs(90))));
+    assert_eq!("30m".parse(), Ok(Retention::Age(Duration::from_secs(1800))));
+    assert_eq!("2h".parse(), Ok(Retention::Age(Duration::from_secs(7200))));
+
+    for invalid in ["", "-1", "10d", "m", "lots"] {
+        assert!(
+            invalid.parse::<Retention>().is_err(),
+            "{invalid:?} should not parse"
+        );
+    }
+}
+
+///An age based buffer should only evict transactions older than its retention
+#[test]
+fn test_indexed_buffer_age_retention() {
+    use std::time::{Duration, Instant};
+
+    let mut buffer = IndexedBuffer::with_retention(Retention::Age(Duration::from_secs(60)));
+    let start = Instant::now();
+
+    buffer.push_at(stored_deposit(1, "1"), start);
+    buffer.push_at(stored_deposit(2, "1"), start + Duration::from_secs(30));
+
+    assert!(buffer
+        .pop_expired(start + Duration::from_secs(60))
+        .is_none());
+    let evicted = buffer.pop_expired(start + Duration::from_secs(61)).unwrap();
+    assert_eq!(evicted.tx.id, 1);
+    assert!(buffer
+        .pop_expired(start + Duration::from_secs(61))
+        .is_none());
+    assert!(buffer.get_by_tx(2).is_some());
+}
+
+///An unbounded buffer should never evict transactions
+#[test]
+fn test_indexed_buffer_unbounded() {
+    let mut buffer = IndexedBuffer::with_retention(Retention::Unbounded);
+
+    for id in 0..50_000 {
+        assert!(buffer.push(stored_deposit(id, "1")).is_none());
+    }
+    assert_eq!(buffer.len(), 50_000);
+    assert!(buffer.get_by_tx(0).is_some());
+}
+
+///Disputes of evicted transactions should fail with a specific error and be counted
+#[test]
+fn test_evicted_dispute_failures() {
+    let mut ledger = Ledger::with_capacity(2);
+
+    for id in 1..=3 {
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id,
+            amount: Some(amt("1")),
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    let result = apply_action(&mut ledger, TransactionType::Dispute, 1);
+    assert!(matches!(
+        result,
+        Err(ProcessError::EvictedTransaction { .. })
+    ));
+
+    //A transaction which never existed isn't counted as evicted
+    let result = apply_action(&mut ledger, TransactionType::Dispute, 4);
+    assert!(matches!(
+        result,
+        Err(ProcessError::UnknownTransaction { .. })
+    ));
+
+    apply_action(&mut ledger, TransactionType::Dispute, 3).unwrap();
+    assert_eq!(ledger.evicted_dispute_failures(), 1);
+}
+
+///DiskStore should return the latest version of every transaction, both before
+///and after its buffered records are flushed, and after being reopened
+#[test]
+fn test_disk_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = DiskStore::create(dir.path()).unwrap();
+
+    //Enough transactions to be flushed at least once, with IDs spread far apart
+    let ids: Vec<u32> = (0..10_000).map(|i| i * 100_003).collect();
+    for id in &ids {
+        assert!(store.push(stored_deposit(*id, "1.5")).unwrap().is_none());
+    }
+    store.set_state(ids[0], TxState::Disputed).unwrap();
+    store.set_state(ids[9_999], TxState::Resolved).unwrap();
+
+    let stored = store.get(ids[0]).unwrap().unwrap();
+    assert_eq!(stored.tx.amount, Some(amt("1.5")));
+    assert_eq!(stored.state, TxState::Disputed);
+    assert!(store.get(1).unwrap().is_none());
+    assert!(store.get(u32::MAX).unwrap().is_none());
+
+    drop(store);
+    let store = DiskStore::open(dir.path()).unwrap();
+    for id in &ids {
+        assert_eq!(store.get(*id).unwrap().unwrap().tx.id, *id);
+    }
+    assert_eq!(store.get(ids[0]).unwrap().unwrap().state, TxState::Disputed);
+    assert_eq!(
+        store.get(ids[9_999]).unwrap().unwrap().state,
+        TxState::Resolved
+    );
+    assert_eq!(
+        store.get(ids[5]).unwrap().unwrap().state,
+        TxState::Processed
+    );
+
+    //Creating a store again should start from empty
+    drop(store);
+    let store = DiskStore::create(dir.path()).unwrap();
+    assert!(store.get(ids[0]).unwrap().is_none());
+}
+
+///A DiskStore opened read only should see what's already stored and what's
+///stored since, without ever changing the files
+#[test]
+fn test_disk_store_read_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = DiskStore::create(dir.path()).unwrap();
+    store.push(stored_deposit(1, "1.0")).unwrap();
+    drop(store);
+    let files = ["transactions.dat", "transactions.idx"];
+    let contents = || files.map(|name| std::fs::read(dir.path().join(name)).unwrap());
+    let before = contents();
+
+    let mut store = DiskStore::open_read_only(dir.path()).unwrap();
+    //Enough transactions that a writable store would have flushed them
+    for id in 2..10_000 {
+        store.push(stored_deposit(id, "2.0")).unwrap();
+    }
+    store.set_state(1, TxState::Disputed).unwrap();
+    store.flush().unwrap();
+    assert_eq!(store.get(1).unwrap().unwrap().state, TxState::Disputed);
+    assert_eq!(store.get(9_999).unwrap().unwrap().tx.id, 9_999);
+    assert!(store.rollback(16).is_err());
+    drop(store);
+
+    assert_eq!(contents(), before);
+    let store = DiskStore::open(dir.path()).unwrap();
+    assert_eq!(store.get(1).unwrap().unwrap().state, TxState::Processed);
+    assert!(store.get(2).unwrap().is_none());
+}
+
+///A ledger backed by a DiskStore should be able to dispute transactions
+///far older than any in-memory retention would keep
+#[test]
+fn test_ledger_disk_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = DiskStore::create(dir.path()).unwrap();
+    let mut ledger = Ledger::with_store(LedgerConfig::default(), store);
+
+    for id in 1..=50_000 {
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id,
+            amount: Some(amt("1")),
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    assert_eq!(ledger.state(1), Some(TxState::Disputed));
+    apply_action(&mut ledger, TransactionType::Resolve, 1).unwrap();
+    assert_eq!(ledger.state(1), Some(TxState::Resolved));
+    let result = apply_action(&mut ledger, TransactionType::Dispute, 1);
+    assert!(matches!(
+        result,
+        Err(ProcessError::IllegalTransition { .. })
+    ));
+
+    let client = ledger.client(1).unwrap();
+    assert_eq!(client.available, amt("50000"));
+    assert_eq!(client.held, amt("0"));
+    assert_eq!(ledger.evicted_dispute_failures(), 0);
+}
+
+///Clients should be output sorted by ID, by total descending, or in first seen order
+#[test]
+fn test_clients_ordered() {
+    let mut ledger = Ledger::new();
+
+    for (client, id, amount) in [(3, 1, "5"), (1, 2, "10"), (2, 3, "5"), (1, 4, "1")] {
+        let tx = Transaction {
+            tx_type: TransactionType::Deposit,
+            client,
+            id,
+            amount: Some(amt(amount)),
+        };
+        ledger.apply(tx).unwrap();
+    }
+
+    let ids = |order| -> Vec<u16> {
+        let clients = ledger.clients_ordered(order);
+        clients.iter().map(|client| client.client).collect()
+    };
+    assert_eq!(ids(OutputOrder::ClientId), vec![1, 2, 3]);
+    assert_eq!(ids(OutputOrder::TotalDesc), vec![1, 2, 3]);
+    assert_eq!(ids(OutputOrder::FirstSeen), vec![3, 1, 2]);
+
+    assert_eq!("total".parse(), Ok(OutputOrder::TotalDesc));
+    assert!("random".parse::<OutputOrder>().is_err());
+}
+
+///Errors should carry a stable reason code and the offending transaction
+#[test]
+fn test_error_code() {
+    let mut ledger = Ledger::new();
+    let withdrawal = Transaction {
+        tx_type: TransactionType::Withdrawal,
+        client: 1,
+        id: 1,
+        amount: Some(amt("1.0")),
     };
 
-    process_transaction(tx, &mut clients, &mut processed_txs, &mut held_txs).unwrap();
+    let e = ledger.apply(withdrawal).unwrap_err();
+    assert_eq!(e.code(), "insufficient_funds");
+    assert_eq!(e.category(), ErrorCategory::Rule);
+    assert_eq!(e.transaction().map(|tx| tx.id), Some(1));
+
+    let e = apply_action(&mut ledger, TransactionType::Dispute, 2).unwrap_err();
+    assert_eq!(e.code(), "unknown_transaction");
+
+    let e = ledger
+        .apply(Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            id: 3,
+            amount: None,
+        })
+        .unwrap_err();
+    assert_eq!(e.code(), "missing_amount");
+    assert_eq!(e.category(), ErrorCategory::Validation);
+}
+
+///Open disputes should be counted until they're resolved or charged back
+#[test]
+fn test_open_disputes() {
+    let mut ledger = Ledger::new();
+    for id in 1..=2 {
+        let tx = stored_deposit(id, "1.0").tx;
+        ledger.apply(tx).unwrap();
+    }
+    assert_eq!(ledger.open_disputes(), 0);
+
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    apply_action(&mut ledger, TransactionType::Dispute, 2).unwrap();
+    assert_eq!(ledger.open_disputes(), 2);
+
+    apply_action(&mut ledger, TransactionType::Resolve, 1).unwrap();
+    apply_action(&mut ledger, TransactionType::Chargeback, 2).unwrap();
+    assert_eq!(ledger.open_disputes(), 0);
+}
+
+///Client records should load from a snapshot in the output format, and
+///transactions should apply on top of them
+#[test]
+fn test_load_snapshot() {
+    let snapshot = "client,available,held,total,locked\n\
+                    1,1.5,0.5,2.0,false\n\
+                    2,3.0,0.0,3.0,true\n";
+    let mut ledger = Ledger::new();
+    assert_eq!(ledger.load_snapshot(snapshot.as_bytes()).unwrap(), 2);
+    assert_funds(&ledger, "1.5", "0.5", "2.0");
+    assert!(ledger.client(2).unwrap().locked);
+
+    ledger.apply(stored_deposit(1, "1.0").tx).unwrap();
+    assert_funds(&ledger, "2.5", "0.5", "3.0");
+
+    //Client 1 is already in the ledger
+    let e = ledger.load_snapshot(snapshot.as_bytes()).unwrap_err();
+    assert!(matches!(e, SnapshotError::DuplicateClient { client: 1 }));
+
+    let unbalanced = "client,available,held,total,locked\n3,1.0,1.0,1.0,false\n";
+    let e = Ledger::new()
+        .load_snapshot(unbalanced.as_bytes())
+        .unwrap_err();
+    assert!(matches!(e, SnapshotError::Unbalanced { .. }));
+}
+
+///TxIdSet should report its IDs as ranges of consecutive IDs, including across pages
+#[test]
+fn test_tx_id_set_ranges() {
+    let mut set = TxIdSet::new();
+    for id in [1, 2, 3, 5, 65_535, 65_536, u32::MAX] {
+        set.insert(id);
+    }
+    assert_eq!(
+        set.ranges(),
+        vec![(1, 3), (5, 5), (65_535, 65_536), (u32::MAX, u32::MAX)]
+    );
+}
+
+///A ledger restored from a checkpoint should carry on exactly where the
+///checkpointed one left off, including open disputes and duplicate checks
+#[test]
+fn test_checkpoint_restore() {
+    let mut ledger = Ledger::new();
+    ledger.apply(stored_deposit(1, "5.0").tx).unwrap();
+    ledger.apply(stored_deposit(2, "3.0").tx).unwrap();
+    apply_action(&mut ledger, TransactionType::Dispute, 2).unwrap();
+
+    let mut saved = Vec::new();
+    ledger.checkpoint().save(&mut saved).unwrap();
+    let checkpoint = Checkpoint::load(saved.as_slice()).unwrap();
+
+    let mut restored = Ledger::new();
+    restored.restore(checkpoint).unwrap();
+    assert_funds(&restored, "5.0", "3.0", "8.0");
+    assert_eq!(restored.state(2), Some(TxState::Disputed));
+
+    //The open dispute can be resolved, and the retained deposit disputed
+    apply_action(&mut restored, TransactionType::Resolve, 2).unwrap();
+    apply_action(&mut restored, TransactionType::Dispute, 1).unwrap();
+    assert_funds(&restored, "3.0", "5.0", "8.0");
+
+    //IDs processed before the checkpoint are still duplicates
+    let e = restored.apply(stored_deposit(1, "1.0").tx).unwrap_err();
+    assert!(matches!(e, ProcessError::DuplicateTransaction { .. }));
+
+    //Checkpoints in other versions, including earlier ones, are rejected
+    let e = Checkpoint::load(r#"{"version":1}"#.as_bytes()).unwrap_err();
+    assert!(matches!(
+        e,
+        CheckpointError::UnsupportedVersion { version: 1 }
+    ));
+}
+
+///Restoring onto a ledger which has already processed transactions should
+///replace them, rather than keep them alongside the checkpoint's
+#[test]
+fn test_checkpoint_restore_replaces_history() {
+    let mut ledger = Ledger::new();
+    ledger.apply(stored_deposit(1, "5.0").tx).unwrap();
+    let mut saved = Vec::new();
+    ledger.checkpoint().save(&mut saved).unwrap();
+
+    let mut restored = Ledger::new();
+    restored.apply(stored_deposit(2, "3.0").tx).unwrap();
+    restored
+        .restore(Checkpoint::load(saved.as_slice()).unwrap())
+        .unwrap();
+    assert_funds(&restored, "5.0", "0.0", "5.0");
+
+    //The restored ledger checkpoints to exactly what it was restored from
+    let mut resaved = Vec::new();
+    restored.checkpoint().save(&mut resaved).unwrap();
+    assert_eq!(resaved, saved);
+
+    //The transaction processed before restoring is gone
+    let e = apply_action(&mut restored, TransactionType::Dispute, 2).unwrap_err();
+    assert!(matches!(e, ProcessError::UnknownTransaction { .. }));
+    restored.apply(stored_deposit(2, "3.0").tx).unwrap();
+    assert_funds(&restored, "8.0", "0.0", "8.0");
+}
+
+///A checkpoint whose history is kept in a `DiskStore` should only be restored
+///onto that same history, and one with its own history only onto a store kept
+///in memory, so disputes never silently lose the transactions they refer to
+#[test]
+fn test_checkpoint_history_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ledger = Ledger::with_store(
+        LedgerConfig::default(),
+        DiskStore::create(dir.path()).unwrap(),
+    );
+    ledger.apply(stored_deposit(1, "5.0").tx).unwrap();
+    ledger.flush().unwrap();
+    let mut saved = Vec::new();
+    ledger.checkpoint().save(&mut saved).unwrap();
+    drop(ledger);
+    let load = || Checkpoint::load(saved.as_slice()).unwrap();
+
+    let e = Ledger::new().restore(load()).unwrap_err();
+    assert!(matches!(e, CheckpointError::ExternalHistoryMissing));
+
+    //The history was replaced since the checkpoint was taken
+    let other = tempfile::tempdir().unwrap();
+    let store = DiskStore::create(other.path()).unwrap();
+    let e = Ledger::with_store(LedgerConfig::default(), store)
+        .restore(load())
+        .unwrap_err();
+    assert!(matches!(
+        e,
+        CheckpointError::HistoryMismatch {
+            expected: 16,
+            found: 0
+        }
+    ));
+
+    let store = DiskStore::open(dir.path()).unwrap();
+    let mut restored = Ledger::with_store(LedgerConfig::default(), store);
+    restored.restore(load()).unwrap();
+    apply_action(&mut restored, TransactionType::Dispute, 1).unwrap();
+    assert_funds(&restored, "0.0", "5.0", "5.0");
+
+    let mut saved = Vec::new();
+    Ledger::new().checkpoint().save(&mut saved).unwrap();
+    let store = DiskStore::create(other.path()).unwrap();
+    let e = Ledger::with_store(LedgerConfig::default(), store)
+        .restore(Checkpoint::load(saved.as_slice()).unwrap())
+        .unwrap_err();
+    assert!(matches!(e, CheckpointError::ExternalHistoryUnexpected));
+}
+
+///A journal should read back the transactions appended to it, ignoring a last
+///line cut off by a crash, and replaying it should rebuild the ledger's state
+#[test]
+fn test_journal_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.jsonl");
+    assert!(Journal::read(&path).unwrap().is_empty());
+
+    let mut journal = Journal::open(&path, 1).unwrap();
+    journal
+        .append("input.csv", 2, 22, &stored_deposit(1, "5.0").tx)
+        .unwrap();
+    journal
+        .append("input.csv", 3, 40, &stored_deposit(2, "3.0").tx)
+        .unwrap();
+    drop(journal);
+
+    //Simulate a crash part way through writing an entry
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    contents.push_str(r#"{"input":"input.csv","li"#);
+    std::fs::write(&path, contents).unwrap();
+
+    let entries = Journal::read(&path).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[1].line, entries[1].byte), (3, 40));
+
+    let mut ledger = Ledger::new();
+    for entry in entries {
+        ledger.apply(entry.tx).unwrap();
+    }
+    assert_funds(&ledger, "8.0", "0.0", "8.0");
+
+    Journal::clear(&path).unwrap();
+    assert!(Journal::read(&path).unwrap().is_empty());
+}
+
+///Appending to a journal which ends in a line cut off by a crash should drop the
+///cut off line, rather than run the next entry into it
+#[test]
+fn test_journal_append_after_torn_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.jsonl");
+    std::fs::write(&path, r#"{"input":"input.csv","li"#).unwrap();
+    assert!(Journal::read(&path).unwrap().is_empty());
+
+    let mut journal = Journal::open(&path, 1).unwrap();
+    journal
+        .append("input.csv", 2, 22, &stored_deposit(1, "5.0").tx)
+        .unwrap();
+    drop(journal);
+    let entries = Journal::read(&path).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].line, entries[0].byte), (2, 22));
+
+    //Complete entries before a cut off line are kept
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    contents.push_str(r#"{"input":"#);
+    std::fs::write(&path, contents).unwrap();
+    let mut journal = Journal::open(&path, 1).unwrap();
+    journal
+        .append("input.csv", 3, 40, &stored_deposit(2, "3.0").tx)
+        .unwrap();
+    drop(journal);
+    let entries = Journal::read(&path).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[1].line, entries[1].byte), (3, 40));
+}
+
+///Recovering a run which kept its history in a `DiskStore` should roll the store
+///back to the checkpoint the run started from before replaying the journal, so
+///changes which reached the store but not the journal are never applied twice
+#[test]
+fn test_journal_recover_disk_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let history = dir.path().join("history");
+    let journal_path = dir.path().join("journal.jsonl");
+
+    let mut ledger = Ledger::with_store(
+        LedgerConfig::default(),
+        DiskStore::create(&history).unwrap(),
+    );
+    ledger.apply(stored_deposit(1, "5.0").tx).unwrap();
+    ledger.flush().unwrap();
+    let mut saved = Vec::new();
+    ledger.checkpoint().save(&mut saved).unwrap();
+
+    //The next run journals its first transaction, but dies after its dispute
+    //has reached the store and before it has been journaled
+    let mut journal = Journal::open(&journal_path, 1).unwrap();
+    let tx = stored_deposit(2, "3.0").tx;
+    journal.append("day2.csv", 2, 22, &tx).unwrap();
+    ledger.apply(tx).unwrap();
+    apply_action(&mut ledger, TransactionType::Dispute, 1).unwrap();
+    ledger.flush().unwrap();
+    drop(journal);
+    drop(ledger);
+
+    let mut recovered =
+        Ledger::with_store(LedgerConfig::default(), DiskStore::open(&history).unwrap());
+    recovered
+        .restore(Checkpoint::load(saved.as_slice()).unwrap())
+        .unwrap();
+    for entry in Journal::read(&journal_path).unwrap() {
+        recovered.apply(entry.tx).unwrap();
+    }
+    assert_funds(&recovered, "8.0", "0.0", "8.0");
+    assert_eq!(recovered.state(1), Some(TxState::Processed));
 
-    let client = clients.get(&1).unwrap();
-    assert_eq!(client.total, 21.1241);
+    //The dispute which wasn't journaled can be processed again
+    apply_action(&mut recovered, TransactionType::Dispute, 1).unwrap();
+    assert_funds(&recovered, "3.0", "5.0", "8.0");
 }
@@ -0,0 +1,68 @@
+use crate::datatypes::{Amount, AmountErrorKind, Transaction, TransactionRecord, TransactionType};
+use crate::error::ProcessError;
+
+/// Parses the amount of a row read from the input into a transaction
+///
+/// An amount which can't be represented as an `Amount`, such as `NaN`, `inf`, or
+/// anything with more than 4 decimal places, is rejected here with its own
+/// validation error, rather than failing the row as unreadable
+pub fn parse(record: TransactionRecord) -> Result<Transaction, ProcessError> {
+    let TransactionRecord {
+        tx_type,
+        client,
+        id,
+        amount,
+    } = record;
+    let mut tx = Transaction {
+        tx_type,
+        client,
+        id,
+        amount: None,
+    };
+
+    if let Some(amount) = amount {
+        match amount.parse() {
+            Ok(parsed) => tx.amount = Some(parsed),
+            Err(error) => {
+                return Err(match error.kind() {
+                    AmountErrorKind::NotFinite => ProcessError::NonFiniteAmount { tx, amount },
+                    AmountErrorKind::TooPrecise => ProcessError::TooPrecise { tx, amount },
+                    _ => ProcessError::InvalidAmount { tx, error },
+                })
+            }
+        }
+    }
+    Ok(tx)
+}
+
+/// Checks that a deserialized transaction is well formed before it's applied to a ledger,
+/// returning it unchanged if so
+///
+/// Deposits and withdrawals must have a positive amount no larger than `max_amount`,
+/// while disputes, resolves, and chargebacks refer to another transaction's amount
+/// and so must not have one of their own.
+pub fn validate(tx: Transaction, max_amount: Option<Amount>) -> Result<Transaction, ProcessError> {
+    match tx.tx_type {
+        TransactionType::Deposit | TransactionType::Withdrawal => {
+            let Some(amount) = tx.amount else {
+                return Err(ProcessError::MissingAmount { tx });
+            };
+
+            //A negative withdrawal would increase funds, and a zero amount
+            //is never meaningful, so only positive amounts are allowed
+            if amount <= Amount::ZERO {
+                return Err(ProcessError::NonPositiveAmount { tx });
+            }
+
+            if let Some(max) = max_amount.filter(|max| amount > *max) {
+                return Err(ProcessError::AmountTooLarge { tx, max });
+            }
+        }
+        TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+            if tx.amount.is_some() {
+                return Err(ProcessError::UnexpectedAmount { tx });
+            }
+        }
+    }
+    Ok(tx)
+}
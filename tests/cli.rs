@@ -0,0 +1,870 @@
+//! Runs the binary end to end, for the parts of the CLI which can't be reached
+//! from the library's own tests
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+///Run the binary with the given arguments and stdin
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transaction-processor"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    //Nothing reads stdin unless `-` is an input, so the write may fail harmlessly
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+///Write a file into the temporary directory, returning its path
+fn write(dir: &TempDir, name: &str, contents: &str) -> PathBuf {
+    let path = dir.path().join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+///Transactions should be read from stdin when the input is `-`
+#[test]
+fn test_stdin_input() {
+    let output = run(
+        &["-"],
+        "type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+    );
+}
+
+///Giving stdin as an input more than once should be an error, rather than
+///waiting on it forever
+#[test]
+fn test_repeated_stdin_input() {
+    let output = run(&["-", "-"], "type,client,tx,amount\ndeposit,1,1,2.5\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).is_empty());
+    assert!(stderr(&output).contains("`-` can only be given once"));
+}
+
+///Multiple inputs should be processed in order into the same clients, with
+///rejected rows attributed to the input they came from
+#[test]
+fn test_multiple_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = write(
+        &dir,
+        "first.csv",
+        "type,client,tx,amount\ndeposit,1,1,5.0\n",
+    );
+    let second = write(
+        &dir,
+        "second.csv",
+        "type,client,tx,amount\nwithdrawal,1,2,2.0\nwithdrawal,1,3,9.0\n",
+    );
+
+    let output = run(&[arg(&first), "-", arg(&second)], "type,client,tx,amount\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,3.0,0.0,3.0,false\n"
+    );
+    let errors = stderr(&output);
+    assert!(
+        errors.starts_with(&format!("{}:3 ", second.display())),
+        "{errors}"
+    );
+    assert!(errors.contains("Insufficient funds"), "{errors}");
+
+    //A rejected row from stdin is attributed to stdin
+    let output = run(
+        &[arg(&first), "-"],
+        "type,client,tx,amount\nwithdrawal,1,2,9.0\n",
+    );
+    assert!(stderr(&output).starts_with("<stdin>:2 "));
+}
+
+///A missing input should be reported before anything is processed
+#[test]
+fn test_missing_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = write(
+        &dir,
+        "first.csv",
+        "type,client,tx,amount\ndeposit,1,1,5.0\n",
+    );
+    let missing = dir.path().join("missing.csv");
+
+    let output = run(&[arg(&first), arg(&missing)], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).is_empty());
+    assert!(stderr(&output).contains("missing.csv"));
+}
+
+///Client records should be written as a JSON array or as JSON lines, with
+///amounts as strings
+#[test]
+fn test_output_formats() {
+    let input = "type,client,tx,amount\ndeposit,2,1,1.5\ndeposit,1,2,0.0001\n";
+
+    let output = run(&["-", "--format", "json"], input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let clients: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(
+        clients,
+        serde_json::json!([
+            {"client": 1, "available": "0.0001", "held": "0.0", "total": "0.0001", "locked": false},
+            {"client": 2, "available": "1.5", "held": "0.0", "total": "1.5", "locked": false},
+        ])
+    );
+
+    let output = run(&["-", "--format", "jsonl"], input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            r#"{"client":1,"available":"0.0001","held":"0.0","total":"0.0001","locked":false}"#,
+            "\n",
+            r#"{"client":2,"available":"1.5","held":"0.0","total":"1.5","locked":false}"#,
+            "\n"
+        )
+    );
+}
+
+///Rejected rows should be reported with the line and byte offset they start at,
+///including rows after a quoted field spanning lines, in both the error log and
+///the rejects file
+#[test]
+fn test_rejected_row_positions() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write(
+        &dir,
+        "input.csv",
+        "type,client,tx,amount\ndeposit,1,1,5.0\n\"bogus\ntype\",1,2,1.0\nwithdrawal,1,3,9.0\n",
+    );
+    let rejects = dir.path().join("rejects.jsonl");
+
+    let output = run(
+        &[
+            arg(&input),
+            "--rejects",
+            arg(&rejects),
+            "--rejects-format",
+            "jsonl",
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    //The unknown type is quoted in the message, so it spans lines too
+    let errors = stderr(&output);
+    assert!(errors.starts_with(&format!(
+        "{}:3 (byte 38): Could not parse transaction: CSV deserialize error: unknown variant ",
+        input.display()
+    )));
+    assert!(errors.contains(&format!("\n{}:5 (byte 59): ", input.display())));
+
+    let rejections: Vec<serde_json::Value> = fs::read_to_string(&rejects)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let positions: Vec<_> = rejections
+        .iter()
+        .map(|rejection| (&rejection["line"], &rejection["byte"], &rejection["reason"]))
+        .collect();
+    assert_eq!(
+        positions,
+        [
+            (&3.into(), &38.into(), &"parse_error".into()),
+            (&5.into(), &59.into(), &"insufficient_funds".into()),
+        ]
+    );
+    assert_eq!(rejections[0]["row"], "\"bogus\ntype\",1,2,1.0");
+    //The position is in its own fields, rather than repeated in the message
+    let message = rejections[0]["message"].as_str().unwrap();
+    assert!(message.starts_with("Could not parse transaction: CSV deserialize error: unknown"));
+}
+
+///`--summary-file` should count applied and rejected rows per transaction type,
+///and rejections per reason, leaving the client records on stdout
+#[test]
+fn test_summary_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let summary = dir.path().join("summary.txt");
+    let input = concat!(env!("CARGO_MANIFEST_DIR"), "/test_csvs/full_test.csv");
+
+    let output = run(&[input, "--summary-file", arg(&summary)], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("client,available,held,total,locked\n"));
+
+    //The first line has the time taken, which changes from run to run
+    let summary = fs::read_to_string(&summary).unwrap();
+    let (timing, counts) = summary.split_once('\n').unwrap();
+    assert!(timing.starts_with("Processed 26 row(s) in "));
+    assert_eq!(
+        counts,
+        "type             applied    rejected
+deposit               10           0
+withdrawal             6           2
+dispute                3           1
+resolve                1           1
+chargeback             1           1
+Rejections:
+  insufficient_funds: 1
+  locked_account: 1
+  unknown_dispute: 2
+  unknown_transaction: 1
+Clients: 5 (1 locked)
+Open disputes: 1
+"
+    );
+}
+
+const UNCHECKED: &str = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,-1.0
+withdrawal,1,3,100.0
+dispute,1,1,
+";
+
+///`validate` should check every row without applying any, and fail if any
+///were rejected
+#[test]
+fn test_validate() {
+    //The withdrawal is larger than the balance, but that's only found by applying it
+    let output = run(&["validate", "-"], UNCHECKED);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).is_empty());
+    let errors = stderr(&output);
+    let lines: Vec<_> = errors.lines().collect();
+    assert_eq!(lines.len(), 2, "{errors}");
+    assert!(lines[0].starts_with("<stdin>:3 (byte 38): "));
+    assert_eq!(lines[1], "4 row(s) checked, 1 rejected");
+
+    let output = run(&["validate", "-", "--max-amount", "50"], UNCHECKED);
+    assert_eq!(output.status.code(), Some(1));
+    let errors = stderr(&output);
+    assert!(errors.contains("\n<stdin>:4 (byte 55): "));
+    assert!(errors.ends_with("4 row(s) checked, 2 rejected\n"));
+
+    let output = run(&["validate", "-", "--max-amount", "100"], DAY_ONE);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stderr(&output), "2 row(s) checked, 0 rejected\n");
+}
+
+///`--error-log` should get the rejected rows instead of stderr, for both
+///`validate` and `process`
+#[test]
+fn test_error_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let error_log = dir.path().join("errors.log");
+
+    let output = run(
+        &[
+            "validate",
+            "-",
+            "--max-amount",
+            "50",
+            "--error-log",
+            arg(&error_log),
+        ],
+        UNCHECKED,
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "4 row(s) checked, 2 rejected\n");
+    let logged = fs::read_to_string(&error_log).unwrap();
+    let lines: Vec<_> = logged.lines().collect();
+    assert_eq!(lines.len(), 2, "{logged}");
+    assert!(lines[0].starts_with("<stdin>:3 (byte 38): "));
+    assert!(lines[1].starts_with("<stdin>:4 (byte 55): "));
+
+    //Processing also rejects the withdrawal, for the funds it doesn't have
+    let output = run(&["-", "--error-log", arg(&error_log)], UNCHECKED);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).is_empty());
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,0.0,5.0,5.0,false\n"
+    );
+    let logged = fs::read_to_string(&error_log).unwrap();
+    let lines: Vec<_> = logged.lines().collect();
+    assert_eq!(lines.len(), 2, "{logged}");
+    assert!(lines[0].starts_with("<stdin>:3 (byte 38): "));
+    assert!(lines[1].starts_with("<stdin>:4 (byte 55): "));
+}
+
+const DAY_ONE: &str = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+";
+
+const DAY_TWO: &str = "type,client,tx,amount
+withdrawal,1,3,1.0
+dispute,1,1,
+deposit,2,4,2.0
+";
+
+///`as-of` should stop right before the first row with the given transaction ID,
+///or before the given row counted across every input
+#[test]
+fn test_as_of() {
+    let dir = tempfile::tempdir().unwrap();
+    let day_one = write(&dir, "day1.csv", DAY_ONE);
+    let day_two = write(&dir, "day2.csv", DAY_TWO);
+    let inputs = [arg(&day_one), arg(&day_two)];
+
+    let output = run(
+        &[&["as-of"], &inputs[..], &["--before-tx", "1"]].concat(),
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    //Transaction 1 is first seen as the deposit, before which there are no clients
+    assert!(stdout(&output).is_empty());
+
+    let output = run(
+        &[&["as-of"], &inputs[..], &["--before-tx", "4"]].concat(),
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,-1.0,5.0,4.0,false\n2,3.0,0.0,3.0,false\n"
+    );
+    assert!(stderr(&output).contains(&format!("stopped before {}:4 ", day_two.display())));
+
+    //Row 4 is the second row of the second input
+    let output = run(
+        &[
+            &["as-of"],
+            &inputs[..],
+            &["--before-row", "4", "--client", "1"],
+        ]
+        .concat(),
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,4.0,0.0,4.0,false\n"
+    );
+    assert!(stderr(&output).contains(&format!("stopped before {}:3 ", day_two.display())));
+
+    //Never reaching the transaction or row is an error, rather than the final state
+    for stop in [["--before-tx", "99"], ["--before-row", "6"]] {
+        let output = run(&[&["as-of"], &inputs[..], &stop].concat(), "");
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stdout(&output).is_empty());
+    }
+
+    //A client with no record at that point is an error
+    let output = run(
+        &[
+            &["as-of"],
+            &inputs[..],
+            &["--before-row", "2", "--client", "2"],
+        ]
+        .concat(),
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
+
+///Commands which replay rows should refuse age retention, since what it evicts
+///depends on when the rows are replayed
+#[test]
+fn test_replay_rejects_age_retention() {
+    let dir = tempfile::tempdir().unwrap();
+    let day_one = write(&dir, "day1.csv", DAY_ONE);
+    let journal = write(&dir, "journal.jsonl", "");
+    let checkpoint = dir.path().join("checkpoint.json");
+
+    let commands = [
+        vec!["as-of", arg(&day_one), "--before-row", "2"],
+        vec!["statement", arg(&day_one), "--client", "1"],
+        vec!["recover", arg(&journal), "--checkpoint", arg(&checkpoint)],
+    ];
+    for command in commands {
+        let output = run(&[&command[..], &["--retention", "30m"]].concat(), "");
+        assert_eq!(output.status.code(), Some(1), "{command:?}");
+        assert!(stderr(&output).contains("--retention 1800s"), "{command:?}");
+        assert!(stdout(&output).is_empty());
+    }
+    assert!(!checkpoint.exists());
+
+    //Processing new input can still use it
+    let output = run(&[arg(&day_one), "--retention", "30m"], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+///Process day one with a history directory and checkpoint, returning the
+///paths of the inputs, directory, and checkpoint
+fn checkpointed_history(dir: &TempDir) -> (PathBuf, PathBuf, PathBuf) {
+    let day_one = write(dir, "day1.csv", DAY_ONE);
+    let day_two = write(dir, "day2.csv", DAY_TWO);
+    let history = dir.path().join("history");
+    let checkpoint = dir.path().join("checkpoint.json");
+    let output = run(
+        &[
+            arg(&day_one),
+            "--history-dir",
+            arg(&history),
+            "--checkpoint",
+            arg(&checkpoint),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    (day_two, history, checkpoint)
+}
+
+///A run which fails to write its output should leave the checkpoint it resumed
+///from as it was, so its journal can still be recovered onto it
+#[test]
+fn test_failed_output_keeps_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let day_one = write(&dir, "day1.csv", DAY_ONE);
+    let day_two = write(&dir, "day2.csv", DAY_TWO);
+    let checkpoint = dir.path().join("checkpoint.json");
+    let journal = dir.path().join("journal.jsonl");
+    let output = run(&[arg(&day_one), "--checkpoint", arg(&checkpoint)], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let day_one_state = fs::read(&checkpoint).unwrap();
+
+    //The same checkpoint is resumed from and saved to, as a daily run would
+    let missing = dir.path().join("missing").join("output.csv");
+    let output = run(
+        &[
+            arg(&day_two),
+            "--resume",
+            arg(&checkpoint),
+            "--checkpoint",
+            arg(&checkpoint),
+            "--journal",
+            arg(&journal),
+            "--output",
+            arg(&missing),
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read(&checkpoint).unwrap(), day_one_state);
+    assert_eq!(fs::read_to_string(&journal).unwrap().lines().count(), 3);
+
+    let output = run(
+        &[
+            "recover",
+            arg(&journal),
+            "--resume",
+            arg(&checkpoint),
+            "--checkpoint",
+            arg(&checkpoint),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(
+        &["-", "--resume", arg(&checkpoint)],
+        "type,client,tx,amount\n",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,-1.0,5.0,4.0,false\n2,5.0,0.0,5.0,false\n"
+    );
+}
+
+///A run killed part way through should leave a journal which the next run refuses
+///to overwrite, and which `recover` turns into a checkpoint to resume from
+#[test]
+fn test_recover() {
+    let dir = tempfile::tempdir().unwrap();
+    let day_one = write(&dir, "day1.csv", DAY_ONE);
+    let checkpoint = dir.path().join("checkpoint.json");
+    let journal = dir.path().join("journal.jsonl");
+    let output = run(&[arg(&day_one), "--checkpoint", arg(&checkpoint)], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    //Feed the run the first two rows of the day, and kill it once they're journaled
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transaction-processor"))
+        .args([
+            "-",
+            "--resume",
+            arg(&checkpoint),
+            "--checkpoint",
+            arg(&checkpoint),
+            "--journal",
+            arg(&journal),
+            "--journal-sync-interval",
+            "1",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(b"type,client,tx,amount\nwithdrawal,1,3,1.0\ndispute,1,1,\n")
+        .unwrap();
+    let started = Instant::now();
+    while fs::read_to_string(&journal).map_or(0, |contents| contents.lines().count()) < 2 {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "nothing journaled"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    drop(stdin);
+
+    let rest = "type,client,tx,amount\ndeposit,2,4,2.0\n";
+    let resumed = ["-", "--resume", arg(&checkpoint)];
+    let output = run(
+        &[&resumed[..], &["--journal", arg(&journal)]].concat(),
+        rest,
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("run `recover` on it first"));
+    assert!(stdout(&output).is_empty());
+
+    let output = run(
+        &[
+            "recover",
+            arg(&journal),
+            "--resume",
+            arg(&checkpoint),
+            "--checkpoint",
+            arg(&checkpoint),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "Recovered 2 transaction(s) into {}\n\
+             The last applied row was <stdin>:3 (byte 41), resume with the rows after it\n",
+            checkpoint.display()
+        )
+    );
+    assert_eq!(fs::read_to_string(&journal).unwrap(), "");
+
+    let output = run(
+        &[&resumed[..], &["--journal", arg(&journal)]].concat(),
+        rest,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,-1.0,5.0,4.0,false\n2,5.0,0.0,5.0,false\n"
+    );
+}
+
+///Read every file in a directory, to check it hasn't changed
+fn read_dir(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let contents = fs::read(&path).unwrap();
+            (path, contents)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+///`as-of` should read a history directory without ever writing to it, and
+///refuse one without the checkpoint it belongs to
+#[test]
+fn test_as_of_history_read_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let (day_two, history, checkpoint) = checkpointed_history(&dir);
+    let before = read_dir(&history);
+
+    let output = run(
+        &[
+            "as-of",
+            arg(&day_two),
+            "--before-row",
+            "3",
+            "--resume",
+            arg(&checkpoint),
+            "--history-dir",
+            arg(&history),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "client,available,held,total,locked\n1,-1.0,5.0,4.0,false\n2,3.0,0.0,3.0,false\n"
+    );
+
+    let output = run(
+        &[
+            "as-of",
+            arg(&day_two),
+            "--before-row",
+            "3",
+            "--history-dir",
+            arg(&history),
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(read_dir(&history), before);
+
+    //The history is still whole, so the next day can be processed as normal
+    let output = run(
+        &[
+            arg(&day_two),
+            "--resume",
+            arg(&checkpoint),
+            "--history-dir",
+            arg(&history),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+}
+
+///A statement should list every row of the selected clients in order, applied
+///or rejected, with the client's balances after each one
+#[test]
+fn test_statement() {
+    let dir = tempfile::tempdir().unwrap();
+    let day_one = write(&dir, "day1.csv", DAY_ONE);
+    let day_two = write(
+        &dir,
+        "day2.csv",
+        &format!("{DAY_TWO}withdrawal,1,5,9.0\nbogus,1,6,1.0\nresolve,1,1,\n"),
+    );
+    let statement = dir.path().join("statement.csv");
+
+    let output = run(
+        &[
+            "statement",
+            arg(&day_one),
+            arg(&day_two),
+            "--client",
+            "1",
+            "--output",
+            arg(&statement),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let one = day_one.display();
+    let two = day_two.display();
+    assert_eq!(
+        fs::read_to_string(&statement).unwrap(),
+        format!(
+            "input,line,client,tx,type,amount,status,reason,available,held,total,locked
+{one},2,1,1,deposit,5.0,applied,,5.0,0.0,5.0,false
+{two},2,1,3,withdrawal,1.0,applied,,4.0,0.0,4.0,false
+{two},3,1,1,dispute,,applied,,-1.0,5.0,4.0,false
+{two},5,1,5,withdrawal,9.0,rejected,insufficient_funds,-1.0,5.0,4.0,false
+{two},6,1,6,,,rejected,parse_error,-1.0,5.0,4.0,false
+{two},7,1,1,resolve,,applied,,4.0,0.0,4.0,false
+"
+        )
+    );
+
+    //As JSON lines, with a client whose first row is rejected before it has a record
+    let output = run(
+        &["statement", "-", "--client", "3", "--format", "jsonl"],
+        "type,client,tx,amount\nwithdrawal,3,1,1.0\n",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let line: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(
+        line,
+        serde_json::json!({
+            "input": "<stdin>", "line": 2, "client": 3, "tx": 1, "type": "withdrawal",
+            "amount": "1.0", "status": "rejected", "reason": "insufficient_funds",
+            "available": "0.0", "held": "0.0", "total": "0.0", "locked": false,
+        })
+    );
+}
+
+///A statement should read a history directory without ever writing to it
+#[test]
+fn test_statement_history_read_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let (day_two, history, checkpoint) = checkpointed_history(&dir);
+    let before = read_dir(&history);
+
+    let output = run(
+        &[
+            "statement",
+            arg(&day_two),
+            "--client",
+            "1",
+            "--resume",
+            arg(&checkpoint),
+            "--history-dir",
+            arg(&history),
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    //The dispute finds the deposit from the checkpointed history
+    assert!(stdout(&output).contains(",3,1,1,dispute,,applied,,-1.0,5.0,4.0,false\n"));
+
+    let output = run(
+        &[
+            "statement",
+            arg(&day_two),
+            "--client",
+            "1",
+            "--history-dir",
+            arg(&history),
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(read_dir(&history), before);
+}
+
+///Every applied transaction should produce an event with how it changed the
+///client's balances, and whether it locked the account
+#[test]
+fn test_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let events = dir.path().join("events.csv");
+    let input = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+dispute,1,1,
+chargeback,1,1,
+";
+
+    let output = run(&["-", "--events", arg(&events)], input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(&events).unwrap(),
+        "client,tx,type,available_delta,held_delta,total_delta,available,held,total,locked,became_locked
+1,1,deposit,5.0,0.0,5.0,5.0,0.0,5.0,false,false
+1,1,dispute,-5.0,5.0,0.0,0.0,5.0,5.0,false,false
+1,1,chargeback,0.0,-5.0,-5.0,0.0,0.0,0.0,true,true
+"
+    );
+}
+
+///A strict run which stops at a rejected row should leave no events file behind
+#[test]
+fn test_events_strict() {
+    let dir = tempfile::tempdir().unwrap();
+    let events = dir.path().join("events.csv");
+    let input = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,1.0
+withdrawal,1,3,9.0
+";
+
+    let output = run(&["-", "--strict", "--events", arg(&events)], input);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+///A strict run should stop at the first rejected row with the exit code for its
+///kind of error, leaving nothing but the rejects file behind
+#[test]
+fn test_strict() {
+    let dir = tempfile::tempdir().unwrap();
+    let output_file = dir.path().join("output.csv");
+    let checkpoint = dir.path().join("checkpoint.json");
+    let summary = dir.path().join("summary.txt");
+    let journal = dir.path().join("journal.jsonl");
+    let rejects = dir.path().join("rejects.csv");
+
+    let bad_rows = [
+        ("bogus,1,2,1.0", 3),
+        ("deposit,1,2,-1.0", 4),
+        ("deposit,1,2,NaN", 4),
+        ("withdrawal,1,2,9.0", 5),
+    ];
+    for (bad_row, code) in bad_rows {
+        //The rows after the bad one are also rejected, but never reached
+        let input = format!(
+            "type,client,tx,amount\ndeposit,1,1,5.0\n{bad_row}\nwithdrawal,1,3,9.0\n\
+             deposit,1,1,1.0\n"
+        );
+        let output = run(
+            &[
+                "-",
+                "--strict",
+                "--output",
+                arg(&output_file),
+                "--checkpoint",
+                arg(&checkpoint),
+                "--summary-file",
+                arg(&summary),
+                "--journal",
+                arg(&journal),
+                "--rejects",
+                arg(&rejects),
+            ],
+            &input,
+        );
+        assert_eq!(output.status.code(), Some(code), "{bad_row}");
+        assert!(stdout(&output).is_empty());
+        assert!(stderr(&output)
+            .starts_with("error: stopped at the first rejected row: <stdin>:3 (byte 38): "));
+
+        assert_eq!(fs::read_to_string(&rejects).unwrap().lines().count(), 2);
+        assert!(!output_file.exists());
+        assert!(!checkpoint.exists());
+        assert!(!summary.exists());
+        //Nothing from the run was kept, so it doesn't need recovering
+        assert_eq!(fs::read_to_string(&journal).unwrap(), "");
+    }
+}
+
+///A strict run should exit with its own code when the transaction history can't
+///be read
+#[test]
+fn test_strict_storage_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, history, checkpoint) = checkpointed_history(&dir);
+    //Overwrite the stored deposits with records which can't be decoded
+    let data = history.join("transactions.dat");
+    let len = fs::metadata(&data).unwrap().len() as usize;
+    fs::write(&data, vec![0xff; len]).unwrap();
+
+    let output = run(
+        &[
+            "-",
+            "--strict",
+            "--resume",
+            arg(&checkpoint),
+            "--history-dir",
+            arg(&history),
+        ],
+        "type,client,tx,amount\ndispute,1,1,\n",
+    );
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    assert!(stdout(&output).is_empty());
+}